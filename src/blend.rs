@@ -0,0 +1,138 @@
+pub const DEFAULT_GAMMA: f32 = 2.2;
+pub const DEFAULT_POWER: f32 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl Edge {
+    pub const ALL: [Edge; 4] = [Edge::Left, Edge::Right, Edge::Top, Edge::Bottom];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Edge::Left => "left",
+            Edge::Right => "right",
+            Edge::Top => "top",
+            Edge::Bottom => "bottom",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|e| e.name() == name)
+    }
+}
+
+/// Soft-edge blending along one edge of a window whose projection overlaps
+/// the neighbouring one by `width` pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EdgeBlend {
+    pub width: f32,
+    /// Of the projector: the ramp is corrected so the two overlapping halves
+    /// add up to even brightness in light, not in signal.
+    pub gamma: f32,
+    /// Shape of the ramp: 1 is linear, higher is flatter at both ends.
+    pub power: f32,
+}
+
+impl EdgeBlend {
+    /// How much of the picture is kept at `x` across the overlap, where 0 is
+    /// the window's edge and 1 the inner end of the overlap.
+    pub fn gain(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        let ramp = if x < 0.5 {
+            0.5 * (2.0 * x).powf(self.power)
+        } else {
+            1.0 - 0.5 * (2.0 * (1.0 - x)).powf(self.power)
+        };
+        ramp.powf(1.0 / self.gamma)
+    }
+}
+
+/// The blend of every edge of one window.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Blend {
+    pub left: Option<EdgeBlend>,
+    pub right: Option<EdgeBlend>,
+    pub top: Option<EdgeBlend>,
+    pub bottom: Option<EdgeBlend>,
+    /// Grey added outside the overlaps, so their black matches the overlaps',
+    /// where two projectors' black adds up.
+    pub black_lift: f32,
+}
+
+impl Blend {
+    pub fn edge(&self, edge: Edge) -> Option<&EdgeBlend> {
+        match edge {
+            Edge::Left => self.left.as_ref(),
+            Edge::Right => self.right.as_ref(),
+            Edge::Top => self.top.as_ref(),
+            Edge::Bottom => self.bottom.as_ref(),
+        }
+    }
+
+    pub fn set_edge(&mut self, edge: Edge, blend: Option<EdgeBlend>) {
+        match edge {
+            Edge::Left => self.left = blend,
+            Edge::Right => self.right = blend,
+            Edge::Top => self.top = blend,
+            Edge::Bottom => self.bottom = blend,
+        }
+    }
+
+    /// How far `edge` is covered by its overlap, 0 without one.
+    pub fn overlap(&self, edge: Edge) -> f32 {
+        self.edge(edge).map(|e| e.width).unwrap_or(0.0)
+    }
+}
+
+/// One `--blend` argument: `window:edge:width[:gamma[:power]]`, e.g.
+/// `0:right:240` or `1:left:240:2.4:1.5`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EdgeSpec {
+    pub window: usize,
+    pub edge: Edge,
+    pub blend: EdgeBlend,
+}
+
+impl EdgeSpec {
+    pub fn from_spec(spec: &str) -> Option<Self> {
+        let mut parts = spec.split(':').map(str::trim);
+        let window = parts.next()?.parse().ok()?;
+        let edge = Edge::from_name(parts.next()?)?;
+        let mut number = |default: Option<f32>| match parts.next() {
+            Some(part) => part.parse::<f32>().ok(),
+            None => default,
+        };
+        let blend = EdgeBlend {
+            width: number(None)?,
+            gamma: number(Some(DEFAULT_GAMMA))?,
+            power: number(Some(DEFAULT_POWER))?,
+        };
+        let valid = [blend.width, blend.gamma, blend.power]
+            .iter()
+            .all(|v| v.is_finite() && *v > 0.0);
+        if !valid || parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            window,
+            edge,
+            blend,
+        })
+    }
+}
+
+/// One `--black-lift` argument: `window:level`, with the level from 0 to 1.
+pub fn black_lift_from_spec(spec: &str) -> Option<(usize, f32)> {
+    let mut parts = spec.splitn(2, ':').map(str::trim);
+    let window = parts.next()?.parse().ok()?;
+    let level: f32 = parts.next()?.parse().ok()?;
+    if !(0.0..=1.0).contains(&level) {
+        return None;
+    }
+    Some((window, level))
+}
@@ -0,0 +1,91 @@
+use crate::tempo::Tempo;
+
+/// Musical time, independent of how (and how wide) it is drawn.
+///
+/// Views turn `phase` into a pixel offset with their own step size, so zooming
+/// or resizing a window never changes when a step happens.
+#[derive(Debug, Default, Clone)]
+pub struct StepClock {
+    // beats since the start (or the last reset)
+    beats: f64,
+    // how far into the current step we are, 0 up to 1
+    phase: f64,
+}
+
+impl StepClock {
+    /// Runs the clock on for the next `dt` seconds of `tempo`, before the
+    /// tempo itself moves on. Returns how many step boundaries were crossed,
+    /// which can be more than one after a long frame.
+    pub fn advance(&mut self, dt: f32, tempo: &Tempo) -> usize {
+        let steps = tempo.steps_in(dt);
+        self.beats += steps / tempo.subdivision() as f64;
+        self.phase += steps;
+        let crossed = self.phase.floor();
+        self.phase -= crossed;
+        crossed as usize
+    }
+
+    /// Follows an external clock that took `steps` steps and is now at `phase`.
+    pub fn sync_to(&mut self, steps: usize, phase: f32, tempo: &Tempo) {
+        let moved = steps as f64 + phase as f64 - self.phase;
+        self.beats += moved / tempo.subdivision() as f64;
+        self.phase = phase as f64;
+    }
+
+    pub fn beats(&self) -> f64 {
+        self.beats
+    }
+
+    pub fn phase(&self) -> f32 {
+        self.phase as f32
+    }
+
+    /// Back to the start of the current step, keeping the beat count.
+    pub fn rewind_step(&mut self) {
+        self.phase = 0.0;
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance_counts_every_boundary_crossed() {
+        // four steps a second
+        let tempo = Tempo::new(60.0, 4, 0.0);
+        let mut clock = StepClock::default();
+        assert_eq!(clock.advance(0.125, &tempo), 0);
+        assert_eq!(clock.phase(), 0.5);
+        assert_eq!(clock.advance(0.125, &tempo), 1);
+        assert_eq!(clock.phase(), 0.0);
+        assert_eq!(clock.advance(1.0, &tempo), 4);
+        assert_eq!(clock.beats(), 1.25);
+    }
+
+    #[test]
+    fn sync_follows_the_external_phase() {
+        let tempo = Tempo::new(60.0, 4, 0.0);
+        let mut clock = StepClock::default();
+        clock.advance(0.0625, &tempo);
+        clock.sync_to(2, 0.5, &tempo);
+        assert_eq!(clock.phase(), 0.5);
+        assert_eq!(clock.beats(), 0.625);
+    }
+
+    #[test]
+    fn rewind_and_reset() {
+        let tempo = Tempo::new(60.0, 4, 0.0);
+        let mut clock = StepClock::default();
+        clock.advance(0.375, &tempo);
+        clock.rewind_step();
+        assert_eq!(clock.phase(), 0.0);
+        assert_eq!(clock.beats(), 0.375);
+        clock.reset();
+        assert_eq!(clock.beats(), 0.0);
+    }
+}
@@ -0,0 +1,807 @@
+use clap::{Parser, ValueEnum};
+use nannou::color::{rgba, Rgba};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use websocket::native_tls::Certificate;
+
+use green_graph::blend::{self, Blend, EdgeSpec};
+use green_graph::panel::{Panel, Role, Span};
+use green_graph::quantize::Quantize;
+use green_graph::tempo::{self, WheelCurve};
+use green_graph::timeline::{self, MIN_STEPS_ON_SCREEN};
+
+/// Read when it's there and no `--config` says otherwise.
+pub const DEFAULT_PATH: &str = "green_graph.toml";
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_STEPS_ON_SCREEN: usize = 64;
+const DEFAULT_WARP_FILE: &str = "warp.json";
+// the lanes, and the dashed line at "now"
+const DEFAULT_LINE_COLOR: &str = "#008000";
+// a patched cell right after the patch
+const DEFAULT_HIGHLIGHT_COLOR: &str = "#ffffff";
+// the pending matrix
+const DEFAULT_PREVIEW_COLOR: &str = "#00ff0080";
+const DEFAULT_LINE_WEIGHT: f32 = 3.0;
+const DEFAULT_SIDE_LINE_WEIGHT: f32 = 4.0;
+// how often `Watcher` looks at the file
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Projects a step sequencer's timeline across one or more windows.
+///
+/// Every setting can also come from the config file; a flag wins over the
+/// environment, which wins over the file.
+#[derive(Debug, Clone, Default, Parser)]
+#[command(name = "green_graph")]
+pub struct Args {
+    /// The config file [default: green_graph.toml, if there is one]
+    #[arg(long, env = "GREEN_GRAPH_CONFIG", value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Host of the control server
+    #[arg(long, env = "WS_SERVER_IP", value_name = "HOST")]
+    pub server_host: Option<String>,
+    #[arg(long, env = "WS_SERVER_PORT", value_name = "PORT")]
+    pub server_port: Option<u16>,
+    /// How to reach the control server [default: ws]
+    #[arg(long, env = "WS_SERVER_SCHEME", value_name = "SCHEME")]
+    pub server_scheme: Option<Scheme>,
+    /// Certificate (PEM) to trust for wss, the server's own if it signed it
+    /// itself; the system's are trusted without one
+    #[arg(long, env = "WS_SERVER_CA_FILE", value_name = "PATH")]
+    pub server_ca_file: Option<PathBuf>,
+    /// Token to show the server in the handshake, over wss only
+    #[arg(
+        long,
+        env = "WS_SERVER_TOKEN",
+        value_name = "TOKEN",
+        hide_env_values = true
+    )]
+    pub server_token: Option<String>,
+    /// Where the token goes [default: header]
+    #[arg(long, env = "WS_SERVER_TOKEN_IN", value_name = "WHERE")]
+    pub server_token_in: Option<TokenIn>,
+    /// Run the WebSocket server ourselves on ADDR, e.g. 0.0.0.0:8080, for
+    /// controllers to connect to, instead of connecting to a server; plain ws,
+    /// without a token
+    #[arg(long, env = "GREEN_GRAPH_LISTEN", value_name = "ADDR")]
+    pub listen: Option<SocketAddr>,
+    /// Also take OSC messages over UDP on ADDR, e.g. 127.0.0.1:9000, next to
+    /// the WebSocket connection; anyone who can reach it is in control
+    #[arg(long, env = "GREEN_GRAPH_OSC", value_name = "ADDR")]
+    pub osc: Option<SocketAddr>,
+    /// Report the messages we can't apply back to the server;
+    /// --error-replies=false turns it off when the config file has it on. With
+    /// --listen, controllers only hear about messages that couldn't be decoded,
+    /// not the ones the timeline rejects
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    pub error_replies: Option<bool>,
+    /// Width of a window, in steps, before zooming
+    #[arg(long, value_name = "STEPS")]
+    pub steps_on_screen: Option<usize>,
+    /// How many lanes to draw until `/lines` says otherwise
+    #[arg(long, value_name = "COUNT")]
+    pub lines: Option<usize>,
+    /// Where "now" sits in the `now` panel, as a fraction of its width from the right
+    #[arg(long, value_name = "FRACTION")]
+    pub future_position: Option<f32>,
+    /// When a new matrix comes in: immediate, step or cycle
+    #[arg(long, value_name = "BOUNDARY")]
+    pub quantize: Option<String>,
+    /// How `/wheel` values map to BPM, e.g. 0,160,1
+    #[arg(long, value_name = "SPEC")]
+    pub wheel_curve: Option<String>,
+    /// Seconds a tempo change takes
+    #[arg(long, value_name = "SECS")]
+    pub tempo_ramp: Option<f32>,
+    /// Keep generating patterns even while connected; --demo=false turns it
+    /// off when the config file has it on
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    pub demo: Option<bool>,
+    /// Colour of the lanes and of the line at "now", as #rrggbb or #rrggbbaa
+    #[arg(long, value_name = "COLOR")]
+    pub line_color: Option<String>,
+    /// Colour a patched step lights up in
+    #[arg(long, value_name = "COLOR")]
+    pub highlight_color: Option<String>,
+    /// Colour of the matrix waiting to come in
+    #[arg(long, value_name = "COLOR")]
+    pub preview_color: Option<String>,
+    /// Of the lanes in `now` panels, and in every window when spanning
+    #[arg(long, value_name = "PIXELS")]
+    pub line_weight: Option<f32>,
+    /// Of the lanes in `past` and `future` panels
+    #[arg(long, value_name = "PIXELS")]
+    pub side_line_weight: Option<f32>,
+    /// Join the windows into one canvas, optionally picking their order and
+    /// relative widths, e.g. 1:2,0:1
+    #[arg(long, value_name = "SPEC", num_args = 0..=1, default_missing_value = "")]
+    pub span: Option<String>,
+    /// Blend an overlapping edge: window:edge:width[:gamma[:power]]
+    #[arg(long, value_name = "SPEC")]
+    pub blend: Vec<String>,
+    /// Lift a window's black outside its overlaps: window:level
+    #[arg(long, value_name = "SPEC")]
+    pub black_lift: Vec<String>,
+    /// Where the keystone corners are kept [default: warp.json]
+    #[arg(long, value_name = "PATH")]
+    pub warp_file: Option<PathBuf>,
+}
+
+/// One projector window, a `[[window]]` table in the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    /// `past`, `now` or `future`.
+    pub panel: Role,
+    /// Moves the panel by this many steps, negative into the past.
+    pub offset: isize,
+    /// Defaults to the panel's name.
+    pub title: Option<String>,
+    pub size: [u32; 2],
+    /// Index into the monitors the system lists; the system picks without it.
+    pub monitor: Option<usize>,
+    /// Of the top left corner, relative to the monitor's.
+    pub position: Option<[i32; 2]>,
+    pub fullscreen: bool,
+    /// Overlapping edges to blend, each `edge:width[:gamma[:power]]` like
+    /// `--blend` without the window.
+    pub blend: Vec<String>,
+    /// Grey added outside the overlaps, from 0 to 1.
+    pub black_lift: f32,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            panel: Role::Now,
+            offset: 0,
+            title: None,
+            size: [1920, 1080],
+            monitor: None,
+            position: None,
+            fullscreen: false,
+            blend: Vec::new(),
+            black_lift: 0.0,
+        }
+    }
+}
+
+impl WindowConfig {
+    pub fn panel(&self) -> Panel {
+        Panel {
+            role: self.panel,
+            offset: self.offset,
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        self.title.as_deref().unwrap_or_else(|| self.panel.name())
+    }
+
+    /// Whether `other` describes the same window, whatever panel it shows.
+    pub fn same_window(&self, other: &Self) -> bool {
+        self.title() == other.title()
+            && self.size == other.size
+            && self.monitor == other.monitor
+            && self.position == other.position
+            && self.fullscreen == other.fullscreen
+    }
+}
+
+/// The config file as written, everything optional.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct File {
+    warp_file: Option<PathBuf>,
+    server: ServerFile,
+    timeline: TimelineFile,
+    style: StyleFile,
+    #[serde(rename = "window")]
+    windows: Option<Vec<WindowConfig>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerFile {
+    host: Option<String>,
+    port: Option<u16>,
+    scheme: Option<Scheme>,
+    ca_file: Option<PathBuf>,
+    token: Option<String>,
+    token_in: Option<TokenIn>,
+    listen: Option<SocketAddr>,
+    osc: Option<SocketAddr>,
+    error_replies: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TimelineFile {
+    steps_on_screen: Option<usize>,
+    lines: Option<usize>,
+    future_position: Option<f32>,
+    quantize: Option<String>,
+    wheel_curve: Option<String>,
+    tempo_ramp: Option<f32>,
+    demo: Option<bool>,
+    span: Option<SpanFile>,
+}
+
+/// `span = true` for even widths, or a spec like `--span`'s.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum SpanFile {
+    Even(bool),
+    Spec(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct StyleFile {
+    line_color: Option<String>,
+    highlight_color: Option<String>,
+    preview_color: Option<String>,
+    line_weight: Option<f32>,
+    side_line_weight: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    Ws,
+    /// Over TLS.
+    Wss,
+}
+
+impl Scheme {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scheme::Ws => "ws",
+            Scheme::Wss => "wss",
+        }
+    }
+}
+
+/// How the token reaches the server during the handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenIn {
+    /// `Authorization: Bearer <token>`
+    Header,
+    /// `?token=<token>`, for servers that can't read headers
+    Query,
+}
+
+/// The control server to connect to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Server {
+    pub scheme: Scheme,
+    pub host: String,
+    pub port: u16,
+    /// Trusted for `wss` on top of the system's certificates.
+    pub ca_file: Option<PathBuf>,
+    pub token: Option<String>,
+    pub token_in: TokenIn,
+    /// Be the server on this address instead, for controllers to connect to.
+    pub listen: Option<SocketAddr>,
+    /// Also take OSC over UDP on this address, whichever of the two we are.
+    pub osc: Option<SocketAddr>,
+}
+
+impl Server {
+    /// Where the server is, without the token, for the logs.
+    pub fn url(&self) -> String {
+        format!("{}://{}:{}", self.scheme.name(), self.host, self.port)
+    }
+}
+
+/// How the lanes are drawn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Style {
+    pub line_color: Rgba,
+    pub highlight_color: Rgba,
+    pub preview_color: Rgba,
+    pub line_weight: f32,
+    pub side_line_weight: f32,
+}
+
+/// Every setting, from the flags, the environment, the config file and the
+/// defaults, in that order.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The file the settings were read from, if any.
+    pub path: Option<PathBuf>,
+    pub server: Server,
+    pub error_replies: bool,
+    pub steps_on_screen: usize,
+    pub lines: usize,
+    pub future_position: f32,
+    pub quantize: Quantize,
+    pub wheel: WheelCurve,
+    pub tempo_ramp: f32,
+    pub demo: bool,
+    pub style: Style,
+    pub windows: Vec<WindowConfig>,
+    pub span: Option<Span>,
+    // per window
+    pub blends: Vec<Blend>,
+    pub warp_file: PathBuf,
+}
+
+impl Config {
+    /// Reads the file `args` point to, or `DEFAULT_PATH` if that exists, and
+    /// puts `args` on top.
+    pub fn load(args: &Args) -> Result<Self, Error> {
+        let path = match args.config.as_ref() {
+            Some(path) => Some(path.clone()),
+            None if Path::new(DEFAULT_PATH).exists() => Some(PathBuf::from(DEFAULT_PATH)),
+            None => None,
+        };
+        let file = match path.as_ref() {
+            Some(path) => read(path)?,
+            None => File::default(),
+        };
+        let mut config = Self::resolve(args, file)?;
+        config.path = path;
+        Ok(config)
+    }
+
+    /// Puts `args` on top of `file`, the defaults below both, and checks the result.
+    pub fn resolve(args: &Args, file: File) -> Result<Self, Error> {
+        let File {
+            warp_file,
+            server,
+            timeline,
+            style,
+            windows,
+        } = file;
+
+        let scheme = pick(args.server_scheme, server.scheme, Scheme::Ws);
+        let ca_file = args.server_ca_file.clone().or(server.ca_file);
+        if let Some(path) = ca_file.as_ref() {
+            if scheme != Scheme::Wss {
+                return invalid("a server ca_file only makes sense with scheme wss");
+            }
+            check_certificate(path)?;
+        }
+        let token = args.server_token.clone().or(server.token);
+        let listen = args.listen.or(server.listen);
+        if listen.is_some() && (scheme == Scheme::Wss || ca_file.is_some() || token.is_some()) {
+            return invalid(
+                "listen serves plain ws without a token; it can't take scheme wss, ca_file or token",
+            );
+        }
+        if token.as_deref() == Some("") {
+            return invalid("the server token is empty");
+        }
+        if token.is_some() && scheme != Scheme::Wss {
+            return invalid("a server token would go out in the clear; use scheme wss");
+        }
+        let error_replies = pick(args.error_replies, server.error_replies, false);
+        let port = pick(args.server_port, server.port, DEFAULT_PORT);
+        if port == 0 {
+            return invalid("server port can't be 0");
+        }
+        let server = Server {
+            scheme,
+            host: pick(args.server_host.clone(), server.host, DEFAULT_HOST.into()),
+            port,
+            ca_file,
+            token,
+            token_in: pick(args.server_token_in, server.token_in, TokenIn::Header),
+            listen,
+            osc: args.osc.or(server.osc),
+        };
+
+        let steps_on_screen = pick(
+            args.steps_on_screen,
+            timeline.steps_on_screen,
+            DEFAULT_STEPS_ON_SCREEN,
+        );
+        if steps_on_screen < MIN_STEPS_ON_SCREEN {
+            return invalid(format!(
+                "steps_on_screen must be at least {}, not {}",
+                MIN_STEPS_ON_SCREEN, steps_on_screen
+            ));
+        }
+        let lines = pick(args.lines, timeline.lines, timeline::DEFAULT_LINES);
+        if lines == 0 {
+            return invalid("lines must be at least 1");
+        }
+        let future_position = pick(
+            args.future_position,
+            timeline.future_position,
+            timeline::DEFAULT_FUTURE_POSITION,
+        );
+        if !(0.0..1.0).contains(&future_position) {
+            return invalid(format!(
+                "future_position must be from 0 up to 1, not {}",
+                future_position
+            ));
+        }
+        let quantize = match args.quantize.clone().or(timeline.quantize) {
+            Some(name) => match Quantize::from_name(&name) {
+                Some(quantize) => quantize,
+                None => return invalid(format!("invalid quantize {:?}", name)),
+            },
+            None => Quantize::Cycle,
+        };
+        let wheel = match args.wheel_curve.clone().or(timeline.wheel_curve) {
+            Some(spec) => match WheelCurve::from_spec(&spec) {
+                Some(wheel) => wheel,
+                None => return invalid(format!("invalid wheel_curve {:?}", spec)),
+            },
+            None => WheelCurve::default(),
+        };
+        let tempo_ramp = pick(
+            args.tempo_ramp,
+            timeline.tempo_ramp,
+            tempo::DEFAULT_RAMP_SECS,
+        );
+        if !tempo_ramp.is_finite() || tempo_ramp < 0.0 {
+            return invalid(format!("invalid tempo_ramp {}", tempo_ramp));
+        }
+
+        let color = |name: &str, value: Option<String>, default: &str| {
+            let value = value.unwrap_or_else(|| String::from(default));
+            parse_color(&value).ok_or_else(|| {
+                let reason = format!("{} {:?} isn't #rrggbb or #rrggbbaa", name, value);
+                Error::Invalid(reason)
+            })
+        };
+        let weight = |name: &str, value: Option<f32>, default: f32| {
+            let value = value.unwrap_or(default);
+            if value.is_finite() && value > 0.0 {
+                Ok(value)
+            } else {
+                Err(Error::Invalid(format!("invalid {} {}", name, value)))
+            }
+        };
+        let style = Style {
+            line_color: color(
+                "line_color",
+                args.line_color.clone().or(style.line_color),
+                DEFAULT_LINE_COLOR,
+            )?,
+            highlight_color: color(
+                "highlight_color",
+                args.highlight_color.clone().or(style.highlight_color),
+                DEFAULT_HIGHLIGHT_COLOR,
+            )?,
+            preview_color: color(
+                "preview_color",
+                args.preview_color.clone().or(style.preview_color),
+                DEFAULT_PREVIEW_COLOR,
+            )?,
+            line_weight: weight(
+                "line_weight",
+                args.line_weight.or(style.line_weight),
+                DEFAULT_LINE_WEIGHT,
+            )?,
+            side_line_weight: weight(
+                "side_line_weight",
+                args.side_line_weight.or(style.side_line_weight),
+                DEFAULT_SIDE_LINE_WEIGHT,
+            )?,
+        };
+
+        let windows = windows.unwrap_or_else(default_windows);
+        if windows.is_empty() {
+            return invalid("no [[window]] to open");
+        }
+        for (i, window) in windows.iter().enumerate() {
+            if window.size.contains(&0) {
+                return invalid(format!("window {} has size {:?}", i, window.size));
+            }
+        }
+
+        let span = match args.span.clone().map(SpanFile::Spec).or(timeline.span) {
+            None | Some(SpanFile::Even(false)) => None,
+            Some(SpanFile::Even(true)) => Some(Span::even(windows.len())),
+            Some(SpanFile::Spec(spec)) if spec.is_empty() => Some(Span::even(windows.len())),
+            Some(SpanFile::Spec(spec)) => match Span::from_spec(&spec, windows.len()) {
+                Some(span) => Some(span),
+                None => return invalid(format!("invalid span {:?}", spec)),
+            },
+        };
+        // the file's first, so the flags can change single edges
+        let mut blends = vec![Blend::default(); windows.len()];
+        for (i, window) in windows.iter().enumerate() {
+            for spec in &window.blend {
+                match EdgeSpec::from_spec(&format!("{}:{}", i, spec)) {
+                    Some(e) => blends[i].set_edge(e.edge, Some(e.blend)),
+                    None => return invalid(format!("invalid blend {:?} in window {}", spec, i)),
+                }
+            }
+            if !(0.0..=1.0).contains(&window.black_lift) {
+                let lift = window.black_lift;
+                return invalid(format!("invalid black_lift {} in window {}", lift, i));
+            }
+            blends[i].black_lift = window.black_lift;
+        }
+        for spec in &args.blend {
+            match EdgeSpec::from_spec(spec) {
+                Some(e) if e.window < blends.len() => {
+                    blends[e.window].set_edge(e.edge, Some(e.blend))
+                }
+                _ => return invalid(format!("invalid --blend {:?}", spec)),
+            }
+        }
+        for spec in &args.black_lift {
+            match blend::black_lift_from_spec(spec) {
+                Some((window, level)) if window < blends.len() => blends[window].black_lift = level,
+                _ => return invalid(format!("invalid --black-lift {:?}", spec)),
+            }
+        }
+
+        Ok(Self {
+            path: None,
+            server,
+            error_replies,
+            steps_on_screen,
+            lines,
+            future_position,
+            quantize,
+            wheel,
+            tempo_ramp,
+            demo: pick(args.demo, timeline.demo, false),
+            style,
+            windows,
+            span,
+            blends,
+            warp_file: pick(
+                args.warp_file.clone(),
+                warp_file,
+                PathBuf::from(DEFAULT_WARP_FILE),
+            ),
+        })
+    }
+}
+
+/// The mid and right projectors.
+fn default_windows() -> Vec<WindowConfig> {
+    vec![
+        WindowConfig::default(),
+        WindowConfig {
+            panel: Role::Future,
+            ..WindowConfig::default()
+        },
+    ]
+}
+
+fn pick<T>(arg: Option<T>, file: Option<T>, default: T) -> T {
+    arg.or(file).unwrap_or(default)
+}
+
+fn invalid<T, R: Into<String>>(reason: R) -> Result<T, Error> {
+    Err(Error::Invalid(reason.into()))
+}
+
+/// Fails unless `path` holds a PEM certificate, so a typo shows at startup
+/// rather than as a failing handshake.
+fn check_certificate(path: &Path) -> Result<(), Error> {
+    let pem = fs::read(path).map_err(|e| Error::Read(path.to_path_buf(), e))?;
+    match Certificate::from_pem(&pem) {
+        Ok(_) => Ok(()),
+        Err(e) => invalid(format!("{} isn't a PEM certificate: {}", path.display(), e)),
+    }
+}
+
+/// `#rrggbb` or `#rrggbbaa`.
+fn parse_color(text: &str) -> Option<Rgba> {
+    let hex = text.strip_prefix('#')?;
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| {
+        let byte = hex.get(i * 2..i * 2 + 2).unwrap_or("ff");
+        u8::from_str_radix(byte, 16).ok().map(|v| v as f32 / 255.0)
+    };
+    Some(rgba(channel(0)?, channel(1)?, channel(2)?, channel(3)?))
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Read(path, e) => write!(f, "can't read {}: {}", path.display(), e),
+            Error::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Notices when the config file is saved and reads it again, keeping the
+/// flags and the environment on top as at startup.
+pub struct Watcher {
+    args: Args,
+    path: PathBuf,
+    // when the file was last read, `None` while it isn't there
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl Watcher {
+    /// Watches the file `config` was read from, or `DEFAULT_PATH` for when
+    /// one is made.
+    pub fn new(args: Args, config: &Config) -> Self {
+        let path = config
+            .path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_PATH));
+        Self {
+            args,
+            modified: modified(&path),
+            path,
+            checked: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The settings again if the file changed since the last look, which is
+    /// at most every `WATCH_INTERVAL`.
+    pub fn poll(&mut self) -> Option<Result<Config, Error>> {
+        if self.checked.elapsed() < WATCH_INTERVAL {
+            return None;
+        }
+        self.checked = Instant::now();
+        // gone for a moment while an editor saves it; wait for it to come back
+        let modified = modified(&self.path)?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+        let config = read(&self.path).and_then(|file| Config::resolve(&self.args, file));
+        Some(config.map(|config| Config {
+            path: Some(self.path.clone()),
+            ..config
+        }))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reads the config file at `path`, without checking what's in it yet.
+pub fn read(path: &Path) -> Result<File, Error> {
+    let text = fs::read_to_string(path).map_err(|e| Error::Read(path.to_path_buf(), e))?;
+    toml::from_str(&text).map_err(|e| Error::Parse(path.to_path_buf(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(text: &str) -> File {
+        toml::from_str(text).unwrap()
+    }
+
+    fn rejected(args: &Args, text: &str) -> String {
+        match Config::resolve(args, file(text)) {
+            Err(Error::Invalid(reason)) => reason,
+            other => panic!("expected an invalid config, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn defaults_without_flags_or_file() {
+        let config = Config::resolve(&Args::default(), File::default()).unwrap();
+        assert_eq!(config.server.url(), "ws://127.0.0.1:8080");
+        assert_eq!(config.steps_on_screen, DEFAULT_STEPS_ON_SCREEN);
+        assert_eq!(config.quantize, Quantize::Cycle);
+        assert!(!config.demo && !config.error_replies);
+        assert_eq!(config.windows, default_windows());
+        assert_eq!(config.span, None);
+        assert_eq!(config.warp_file, PathBuf::from(DEFAULT_WARP_FILE));
+    }
+
+    #[test]
+    fn flags_win_over_the_file() {
+        let text = r#"
+            warp_file = "file.json"
+            [server]
+            port = 9000
+            host = "file"
+            [timeline]
+            steps_on_screen = 32
+            demo = true
+            quantize = "step"
+        "#;
+        let config = Config::resolve(&Args::default(), file(text)).unwrap();
+        assert_eq!(config.server.url(), "ws://file:9000");
+        assert_eq!(config.steps_on_screen, 32);
+        assert!(config.demo);
+        assert_eq!(config.quantize, Quantize::Step);
+        assert_eq!(config.warp_file, PathBuf::from("file.json"));
+
+        let args = Args {
+            server_port: Some(9001),
+            steps_on_screen: Some(48),
+            demo: Some(false),
+            warp_file: Some(PathBuf::from("flag.json")),
+            ..Args::default()
+        };
+        let config = Config::resolve(&args, file(text)).unwrap();
+        assert_eq!(config.server.url(), "ws://file:9001");
+        assert_eq!(config.steps_on_screen, 48);
+        assert!(!config.demo);
+        assert_eq!(config.quantize, Quantize::Step);
+        assert_eq!(config.warp_file, PathBuf::from("flag.json"));
+    }
+
+    #[test]
+    fn flags_win_over_the_environment() {
+        // the only test that touches the environment, so nothing races it
+        std::env::set_var("WS_SERVER_PORT", "9002");
+        let from_env = Args::try_parse_from(["green_graph"]).unwrap();
+        let from_flag = Args::try_parse_from(["green_graph", "--server-port", "9003"]).unwrap();
+        std::env::remove_var("WS_SERVER_PORT");
+        assert_eq!(from_env.server_port, Some(9002));
+        assert_eq!(from_flag.server_port, Some(9003));
+        let config = Config::resolve(&from_env, file("[server]\nport = 9000")).unwrap();
+        assert_eq!(config.server.port, 9002);
+    }
+
+    #[test]
+    fn bad_settings_are_refused() {
+        let args = Args::default();
+        rejected(&args, "[server]\nport = 0");
+        rejected(&args, "[server]\ntoken = \"\"");
+        rejected(&args, "[server]\ntoken = \"secret\"");
+        let listen = "[server]\nlisten = \"127.0.0.1:8080\"\n";
+        for setting in ["scheme = \"wss\"", "token = \"secret\""] {
+            let reason = rejected(&args, &format!("{}{}", listen, setting));
+            assert!(reason.starts_with("listen"), "{}", reason);
+        }
+        rejected(&args, "[server]\nca_file = \"server.pem\"");
+        rejected(&args, "[timeline]\nsteps_on_screen = 1");
+        rejected(&args, "[timeline]\nlines = 0");
+        rejected(&args, "[timeline]\nfuture_position = 1.0");
+        rejected(&args, "[timeline]\nquantize = \"bar\"");
+        rejected(&args, "[timeline]\nwheel_curve = \"0,2000,1\"");
+        rejected(&args, "[timeline]\ntempo_ramp = -1.0");
+        rejected(&args, "[timeline]\nspan = \"0,0\"");
+        rejected(&args, "[style]\nline_color = \"green\"");
+        rejected(&args, "[style]\nline_weight = 0.0");
+        rejected(&args, "window = []");
+        rejected(&args, "[[window]]\nsize = [0, 1080]");
+        rejected(&args, "[[window]]\nblend = [\"middle:240\"]");
+        rejected(&args, "[[window]]\nblack_lift = 2.0");
+        let args = Args {
+            blend: vec![String::from("2:left:240")],
+            ..Args::default()
+        };
+        rejected(&args, "");
+    }
+
+    #[test]
+    fn unknown_keys_are_refused() {
+        assert!(toml::from_str::<File>("[server]\nhots = \"x\"").is_err());
+        assert!(toml::from_str::<File>("warp = \"x\"").is_err());
+    }
+
+    #[test]
+    fn the_example_file_is_valid() {
+        let text = include_str!("../green_graph.example.toml");
+        let config = Config::resolve(&Args::default(), file(text)).unwrap();
+        assert_eq!(config.windows.len(), 3);
+        assert_eq!(config.warp_file, PathBuf::from(DEFAULT_WARP_FILE));
+    }
+}
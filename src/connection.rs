@@ -0,0 +1,373 @@
+use std::cmp::min;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use websocket::header::{Authorization, Bearer, Headers};
+use websocket::message::OwnedMessage;
+use websocket::native_tls::{self, Certificate, TlsConnector, TlsStream};
+use websocket::result::WebSocketOtherError;
+use websocket::stream::sync::Splittable;
+use websocket::sync::{Reader, Writer};
+use websocket::{ClientBuilder, Message, WebSocketError};
+
+use crate::config::{Scheme, Server, TokenIn};
+use crate::diagnostics::Diagnostics;
+use green_graph::messages;
+use green_graph::messages::{ErrorMessage, Messages};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+// Incoming messages the app hasn't picked up yet. When full, we stop reading
+// from the socket until the app catches up.
+pub const QUEUE_CAPACITY: usize = 256;
+// How long a TLS read holds on to the connection before letting a write in.
+const TLS_READ_SLICE: Duration = Duration::from_millis(50);
+
+/// State of the link to the control server, as last reported by the connection thread.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionStatus {
+    Disconnected,
+    Connecting { attempt: u32 },
+    Connected,
+    Retrying { delay: Duration },
+}
+
+/// The sending half of a connection: messages queued here are written by a
+/// thread of their own, so whoever queues them never waits on the socket.
+pub type Outgoing = SyncSender<Messages>;
+
+/// Either end of a connection to the server, in the clear or over TLS.
+pub enum Stream {
+    Plain(TcpStream),
+    /// A TLS session can't be split like a socket, so both ends share it.
+    /// Reads give it up every `TLS_READ_SLICE` while they wait, so a write
+    /// never waits on the server to send something first.
+    Tls(Arc<Mutex<TlsStream<TcpStream>>>),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let tls = match self {
+            Stream::Plain(stream) => return stream.read(buf),
+            Stream::Tls(tls) => tls,
+        };
+        loop {
+            match tls.lock().unwrap().read(buf) {
+                Err(e) if is_timeout(&e) => (),
+                result => return result,
+            }
+            // room for the writer, which is woken but not let in by the unlock
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            Stream::Tls(tls) => tls.lock().unwrap().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::Tls(tls) => tls.lock().unwrap().flush(),
+        }
+    }
+}
+
+impl Splittable for Stream {
+    type Reader = Stream;
+    type Writer = Stream;
+
+    fn split(self) -> io::Result<(Stream, Stream)> {
+        match self {
+            Stream::Plain(stream) => {
+                Ok((Stream::Plain(stream.try_clone()?), Stream::Plain(stream)))
+            }
+            Stream::Tls(tls) => {
+                tls.lock()
+                    .unwrap()
+                    .get_ref()
+                    .set_read_timeout(Some(TLS_READ_SLICE))?;
+                Ok((Stream::Tls(tls.clone()), Stream::Tls(tls)))
+            }
+        }
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Sent from the connection thread to the app whenever something changes.
+pub enum ConnectionEvent {
+    Status(ConnectionStatus),
+    /// A new connection came up: the app should replace its outgoing queue and message receiver.
+    Connected(Outgoing, Receiver<Messages>),
+}
+
+/// Starts the thread that writes what's queued on the returned `Outgoing` to
+/// `writer`, until every sender is gone or the connection fails.
+pub fn spawn_writer(mut writer: Writer<Stream>) -> Outgoing {
+    let (outgoing, queued) = sync_channel(QUEUE_CAPACITY);
+    std::thread::spawn(move || {
+        for message in queued {
+            if let Err(e) = send(&mut writer, &message) {
+                eprintln!("could not send {}: {}", message.addr(), e);
+                return;
+            }
+        }
+    });
+    outgoing
+}
+
+fn send(writer: &mut Writer<Stream>, message: &Messages) -> Result<(), String> {
+    let json = messages::encode(message).map_err(|e| e.to_string())?;
+    writer
+        .send_message(&Message::text(json))
+        .map_err(|e| e.to_string())
+}
+
+/// Queues `message` on `outgoing` without waiting, logging instead of failing
+/// when the queue is full or the connection is gone.
+pub fn queue(outgoing: &Outgoing, message: Messages) {
+    match outgoing.try_send(message) {
+        Ok(()) => (),
+        Err(TrySendError::Full(message)) => {
+            eprintln!("dropped {}, the connection is behind", message.addr())
+        }
+        Err(TrySendError::Disconnected(message)) => {
+            eprintln!("could not send {}: connection closed", message.addr())
+        }
+    }
+}
+
+/// Exponential backoff between reconnection attempts.
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    /// Returns the delay before the next attempt and doubles it for the one after.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = min(self.current * 2, self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+/// Spawns a thread that keeps (re)connecting to `server` until the app goes away.
+///
+/// The app starts out disconnected; every established connection is handed over
+/// as a `ConnectionEvent::Connected` with a fresh writer and message channel.
+/// Every incoming message is counted in `diagnostics`; with `error_replies` the
+/// server also gets an `/error` message for everything we had to drop.
+pub fn spawn(
+    server: Server,
+    diagnostics: Arc<Mutex<Diagnostics>>,
+    error_replies: bool,
+) -> Receiver<ConnectionEvent> {
+    let (events, recv) = channel();
+    let address = server.url();
+    std::thread::spawn(move || {
+        let tls = match tls_connector(&server) {
+            Ok(tls) => tls,
+            Err(e) => {
+                eprintln!("can't connect to {}: {}", address, e);
+                return;
+            }
+        };
+        let status = |status| events.send(ConnectionEvent::Status(status)).is_ok();
+        let mut backoff = Backoff::new(INITIAL_BACKOFF, MAX_BACKOFF);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            if !status(ConnectionStatus::Connecting { attempt }) {
+                return;
+            }
+            match connect(&server, tls.as_ref()) {
+                Ok((mut reader, writer)) => {
+                    println!("connected to {}", address);
+                    attempt = 0;
+                    backoff.reset();
+                    let outgoing = spawn_writer(writer);
+                    let replies = if error_replies {
+                        Some(outgoing.clone())
+                    } else {
+                        None
+                    };
+                    let (send, recv) = sync_channel(QUEUE_CAPACITY);
+                    let connected = ConnectionEvent::Connected(outgoing, recv);
+                    if events.send(connected).is_err() || !status(ConnectionStatus::Connected) {
+                        return;
+                    }
+                    receive(&mut reader, &diagnostics, replies.as_ref(), |message| {
+                        send.send(message).is_ok()
+                    });
+                    println!("lost connection to {}", address);
+                    if !status(ConnectionStatus::Disconnected) {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("could not connect to {}: {}", address, e);
+                }
+            }
+            let delay = backoff.next_delay();
+            if !status(ConnectionStatus::Retrying { delay }) {
+                return;
+            }
+            std::thread::sleep(delay);
+        }
+    });
+    recv
+}
+
+/// The TLS setup for a `wss` server, trusting its `ca_file` as well as the
+/// system's certificates.
+fn tls_connector(server: &Server) -> Result<Option<TlsConnector>, String> {
+    if server.scheme != Scheme::Wss {
+        return Ok(None);
+    }
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = server.ca_file.as_ref() {
+        let pem = fs::read(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        let certificate = Certificate::from_pem(&pem).map_err(|e| e.to_string())?;
+        builder.add_root_certificate(certificate);
+    }
+    builder.build().map(Some).map_err(|e| e.to_string())
+}
+
+/// Opens a connection to `server`, over TLS with `tls`, and shakes hands,
+/// showing the token if there is one.
+fn connect(
+    server: &Server,
+    tls: Option<&TlsConnector>,
+) -> Result<(Reader<Stream>, Writer<Stream>), String> {
+    let mut url = format!("{}/", server.url());
+    let mut headers = Headers::new();
+    match (server.token.as_ref(), server.token_in) {
+        (Some(token), TokenIn::Header) => headers.set(Authorization(Bearer {
+            token: token.clone(),
+        })),
+        (Some(token), TokenIn::Query) => url = format!("{}?token={}", url, percent_encode(token)),
+        (None, _) => (),
+    }
+    let mut builder = ClientBuilder::new(&url)
+        .map_err(|e| e.to_string())?
+        .custom_headers(&headers);
+
+    let tcp = TcpStream::connect((server.host.as_str(), server.port)).map_err(|e| e.to_string())?;
+    let stream = match tls {
+        Some(tls) => {
+            let session = tls.connect(&server.host, tcp).map_err(|e| match e {
+                native_tls::HandshakeError::Failure(e) => {
+                    format!("TLS handshake failed, is the right ca_file set? {}", e)
+                }
+                e => format!("TLS handshake failed: {}", e),
+            })?;
+            Stream::Tls(Arc::new(Mutex::new(session)))
+        }
+        None => Stream::Plain(tcp),
+    };
+    let client = builder
+        .connect_on(stream)
+        .map_err(|e| handshake_error(e, server))?;
+    client.split().map_err(|e| e.to_string())
+}
+
+/// Why the server wouldn't upgrade the connection, spelling out the token
+/// problems.
+fn handshake_error(e: WebSocketError, server: &Server) -> String {
+    let status = match &e {
+        WebSocketError::Other(other) => match other.downcast_ref::<WebSocketOtherError>() {
+            Some(WebSocketOtherError::StatusCodeError(status)) => *status,
+            _ => return e.to_string(),
+        },
+        _ => return e.to_string(),
+    };
+    match (status.to_u16(), server.token.is_some()) {
+        (401, false) | (403, false) => format!(
+            "the server wants a token ({}); set --server-token, WS_SERVER_TOKEN or token in [server]",
+            status
+        ),
+        (401, true) | (403, true) => format!(
+            "the server turned the token down ({}); check it, and whether token_in should be {}",
+            status,
+            match server.token_in {
+                TokenIn::Header => "query",
+                TokenIn::Query => "header",
+            }
+        ),
+        _ => format!("the server answered {} instead of upgrading", status),
+    }
+}
+
+/// `text` made safe for a URL query.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Hands incoming messages to `forward` until the connection closes or fails,
+/// or `forward` returns false.
+pub fn receive<F>(
+    reader: &mut Reader<Stream>,
+    diagnostics: &Mutex<Diagnostics>,
+    replies: Option<&Outgoing>,
+    mut forward: F,
+) where
+    F: FnMut(Messages) -> bool,
+{
+    loop {
+        let msg = match reader.recv_message() {
+            Ok(OwnedMessage::Text(msg)) => msg,
+            Ok(OwnedMessage::Close(_)) | Err(_) => return,
+            Ok(_) => continue,
+        };
+        let result = messages::decode(&msg);
+        diagnostics.lock().unwrap().record(&msg, &result);
+        match result {
+            Ok(internal_msg) => {
+                if !forward(internal_msg) {
+                    return;
+                }
+            }
+            Err(e) => {
+                if let Some(outgoing) = replies {
+                    let reason = e.to_string();
+                    queue(outgoing, Messages::Error(ErrorMessage { reason }));
+                }
+            }
+        }
+    }
+}
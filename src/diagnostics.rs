@@ -0,0 +1,70 @@
+use std::collections::BTreeMap;
+
+use green_graph::messages::{Error, Messages};
+
+// Raw payloads longer than this are cut off in the log.
+const MAX_LOGGED_PAYLOAD: usize = 200;
+
+/// Message counts for a single address.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Counters {
+    /// Decoded and passed on to the app.
+    pub received: u64,
+    /// Had the right `addr`, but a missing or mistyped field.
+    pub invalid: u64,
+}
+
+/// Keeps track of everything the server sent, including what we couldn't use.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    // only addresses we know, so a peer can't make it grow
+    by_addr: BTreeMap<String, Counters>,
+    /// Messages with an address nobody here knows, whichever it was.
+    pub unknown: u64,
+    /// Payloads that weren't JSON or had no `addr` at all, and OSC packets we
+    /// couldn't read.
+    pub malformed: u64,
+}
+
+impl Diagnostics {
+    /// Counts a decoded (or failed) message and logs failures with their raw payload.
+    pub fn record(&mut self, raw: &str, result: &Result<Messages, Error>) {
+        match result {
+            Ok(message) => self.entry(message.addr()).received += 1,
+            Err(error) => {
+                match error {
+                    Error::UnknownAddr(_) => self.unknown += 1,
+                    Error::Field { addr, .. } => self.entry(addr).invalid += 1,
+                    Error::Syntax(_) | Error::MissingAddr | Error::Encode(_) => self.malformed += 1,
+                }
+                eprintln!("dropped message: {} (payload: {})", error, truncate(raw));
+            }
+        }
+    }
+
+    /// Counts a packet that didn't even get as far as a message.
+    pub fn record_malformed(&mut self, error: &str) {
+        self.malformed += 1;
+        eprintln!("dropped message: {}", error);
+    }
+
+    /// Prints all counters, one address per line.
+    pub fn print(&self) {
+        println!("malformed: {}", self.malformed);
+        println!("unknown addresses: {}", self.unknown);
+        for (addr, c) in self.by_addr.iter() {
+            println!("{}: received {}, invalid {}", addr, c.received, c.invalid);
+        }
+    }
+
+    fn entry(&mut self, addr: &str) -> &mut Counters {
+        self.by_addr.entry(addr.to_string()).or_default()
+    }
+}
+
+fn truncate(raw: &str) -> &str {
+    match raw.char_indices().nth(MAX_LOGGED_PAYLOAD) {
+        Some((end, _)) => &raw[..end],
+        None => raw,
+    }
+}
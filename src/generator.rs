@@ -0,0 +1,112 @@
+use rand::Rng;
+use std::cmp::max;
+
+use crate::matrix::Matrix;
+
+/// How many cycles a freshly generated pattern is kept before the next one replaces it.
+const CYCLES_PER_PATTERN: usize = 4;
+
+/// Pattern generators available in demo mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    /// Every cell is set with the given probability.
+    RandomDensity { density: f64 },
+    /// Every row spreads a random number of pulses as evenly as possible over the cycle.
+    Euclidean,
+    /// Starts from the current pattern and flips cells with the given probability every cycle.
+    Mutate { rate: f64 },
+}
+
+impl Algorithm {
+    pub const ALL: [Algorithm; 3] = [
+        Algorithm::RandomDensity { density: 0.4 },
+        Algorithm::Euclidean,
+        Algorithm::Mutate { rate: 0.05 },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::RandomDensity { .. } => "random density",
+            Algorithm::Euclidean => "euclidean",
+            Algorithm::Mutate { .. } => "mutate",
+        }
+    }
+}
+
+/// Produces matrix content locally when no control server feeds us.
+#[derive(Default)]
+pub struct Generator {
+    algorithm: usize,
+    cycles: usize,
+}
+
+impl Generator {
+    pub fn algorithm(&self) -> Algorithm {
+        Algorithm::ALL[self.algorithm]
+    }
+
+    /// Switches to the next algorithm in `Algorithm::ALL`.
+    pub fn next_algorithm(&mut self) -> Algorithm {
+        self.algorithm = (self.algorithm + 1) % Algorithm::ALL.len();
+        self.cycles = 0;
+        self.algorithm()
+    }
+
+    /// Generates a `rows` x `steps` matrix; both must be at least 1.
+    ///
+    /// `previous` is the pattern currently shown; `Mutate` builds on it when it
+    /// has the same shape.
+    pub fn generate(&mut self, rows: usize, steps: usize, previous: &Matrix) -> Matrix {
+        let mut rng = rand::thread_rng();
+        let len = rows * steps;
+        let same_shape = previous.rows() == rows && previous.cols() == steps;
+        let cells = match self.algorithm() {
+            Algorithm::RandomDensity { density } => {
+                (0..len).map(|_| rng.gen_bool(density) as i32).collect()
+            }
+            Algorithm::Euclidean => {
+                let mut matrix = Vec::with_capacity(len);
+                for _ in 0..rows {
+                    let pulses = rng.gen_range(1..=max(steps / 2, 1));
+                    let rotation = rng.gen_range(0..max(steps, 1));
+                    let row = euclidean(pulses, steps);
+                    matrix.extend((0..steps).map(|i| row[(i + rotation) % steps]));
+                }
+                matrix
+            }
+            Algorithm::Mutate { rate } => (0..len)
+                .map(|i| {
+                    let value = if same_shape { previous.cells()[i] } else { 0 };
+                    if rng.gen_bool(rate) {
+                        1 - value.min(1)
+                    } else {
+                        value
+                    }
+                })
+                .collect(),
+        };
+        Matrix::new(rows, steps, cells).expect("generated matrix has the requested shape")
+    }
+
+    /// Called whenever the matrix cycle wraps around. Returns a new pattern once
+    /// it is time for one: `Mutate` evolves every cycle, the others every few cycles.
+    pub fn on_cycle(&mut self, rows: usize, steps: usize, previous: &Matrix) -> Option<Matrix> {
+        self.cycles += 1;
+        let due = match self.algorithm() {
+            Algorithm::Mutate { .. } => true,
+            _ => self.cycles.is_multiple_of(CYCLES_PER_PATTERN),
+        };
+        if due {
+            Some(self.generate(rows, steps, previous))
+        } else {
+            None
+        }
+    }
+}
+
+/// Distributes `pulses` onsets as evenly as possible over `steps` (Bjorklund's rhythm).
+pub fn euclidean(pulses: usize, steps: usize) -> Vec<i32> {
+    (0..steps)
+        .map(|i| ((i * pulses) % steps < pulses) as i32)
+        .collect()
+}
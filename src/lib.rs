@@ -0,0 +1,17 @@
+//! The sequencer core behind the projections: matrix, tempo, clocks and the
+//! step timeline, without any windowing or graphics.
+
+pub mod blend;
+pub mod clock;
+pub mod generator;
+pub mod matrix;
+pub mod messages;
+pub mod osc;
+pub mod panel;
+pub mod quantize;
+pub mod ring;
+pub mod sync;
+pub mod tempo;
+pub mod timeline;
+pub mod transport;
+pub mod warp;
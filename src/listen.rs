@@ -0,0 +1,140 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use websocket::sync::server::upgrade::IntoWs;
+
+use crate::connection::{self, ConnectionEvent, ConnectionStatus, Stream, QUEUE_CAPACITY};
+use crate::diagnostics::Diagnostics;
+use green_graph::matrix::Matrix;
+use green_graph::messages::{MatrixMessage, Messages};
+
+/// What controllers get for `/get-matrix`; the app keeps it up to date.
+pub type SharedMatrix = Arc<Mutex<Matrix>>;
+
+/// The app's ends of our own server.
+pub struct Listener {
+    /// `Connected` once a controller is, `Disconnected` when the last one leaves.
+    pub events: Receiver<ConnectionEvent>,
+    /// From every controller, in the order they came in.
+    pub messages: Receiver<Messages>,
+}
+
+/// Runs the WebSocket server on `address` ourselves, for controllers to
+/// connect to instead of us connecting to a server, as many as like. It's
+/// plain ws with no token, which is why the config won't take those with `listen`.
+///
+/// Their messages all end up in `Listener::messages`, except `/get-matrix`,
+/// which is answered right away with `matrix`. Every incoming message is counted in
+/// `diagnostics`; with `error_replies` a controller also gets an `/error`
+/// message for everything of its own we couldn't decode. What the timeline
+/// rejects later is only logged.
+pub fn spawn(
+    address: SocketAddr,
+    matrix: SharedMatrix,
+    diagnostics: Arc<Mutex<Diagnostics>>,
+    error_replies: bool,
+) -> io::Result<Listener> {
+    let listener = TcpListener::bind(address)?;
+    println!("listening for controllers on {}", address);
+    let (events, events_recv) = channel();
+    let (send, messages) = sync_channel(QUEUE_CAPACITY);
+    let connected = Arc::new(Mutex::new(0));
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("could not accept a controller: {}", e);
+                    continue;
+                }
+            };
+            let controller = Controller {
+                events: events.clone(),
+                send: send.clone(),
+                matrix: matrix.clone(),
+                connected: connected.clone(),
+                diagnostics: diagnostics.clone(),
+                error_replies,
+            };
+            std::thread::spawn(move || controller.serve(stream));
+        }
+    });
+    Ok(Listener {
+        events: events_recv,
+        messages,
+    })
+}
+
+/// One controller's connection, and what it shares with the others.
+struct Controller {
+    events: Sender<ConnectionEvent>,
+    send: SyncSender<Messages>,
+    matrix: SharedMatrix,
+    // how many controllers are connected; held while sending the status, so
+    // a join and a leave at the same time can't swap their events
+    connected: Arc<Mutex<usize>>,
+    diagnostics: Arc<Mutex<Diagnostics>>,
+    error_replies: bool,
+}
+
+impl Controller {
+    /// Shakes hands and forwards the controller's messages until it goes away.
+    fn serve(self, stream: TcpStream) {
+        let peer = stream
+            .peer_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_else(|_| String::from("?"));
+        let client = Stream::Plain(stream)
+            .into_ws()
+            .map_err(|(_, _, _, e)| e.to_string())
+            .and_then(|upgrade| upgrade.accept().map_err(|(_, e)| e.to_string()))
+            .and_then(|client| client.split().map_err(|e| e.to_string()));
+        let (mut reader, writer) = match client {
+            Ok(client) => client,
+            Err(e) => {
+                eprintln!("controller {} could not connect: {}", peer, e);
+                return;
+            }
+        };
+        let outgoing = connection::spawn_writer(writer);
+        println!("controller {} connected", peer);
+        self.count(1);
+
+        let replies = if self.error_replies {
+            Some(&outgoing)
+        } else {
+            None
+        };
+        connection::receive(
+            &mut reader,
+            &self.diagnostics,
+            replies,
+            |message| match message {
+                Messages::GetMatrix => {
+                    let matrix = MatrixMessage::from(&*self.matrix.lock().unwrap());
+                    connection::queue(&outgoing, Messages::Matrix(matrix));
+                    true
+                }
+                message => self.send.send(message).is_ok(),
+            },
+        );
+
+        println!("controller {} left", peer);
+        self.count(-1);
+    }
+
+    /// Adds `change` to the controllers connected, and tells the app when
+    /// the first one came or the last one left.
+    fn count(&self, change: isize) {
+        let mut connected = self.connected.lock().unwrap();
+        let was = *connected;
+        *connected = was.saturating_add_signed(change);
+        let status = match (was, *connected) {
+            (0, 1) => ConnectionStatus::Connected,
+            (1, 0) => ConnectionStatus::Disconnected,
+            _ => return,
+        };
+        let _ = self.events.send(ConnectionEvent::Status(status));
+    }
+}
@@ -1,198 +1,453 @@
+use clap::Parser;
 use nannou::prelude::*;
-use rand::Rng;
-use serde_json;
-use std::cmp::{max, min};
-use std::sync::mpsc::{channel, Receiver};
-use websocket::client::sync::Client;
-use websocket::message::OwnedMessage;
-use websocket::sync::stream::TcpStream;
-use websocket::{ClientBuilder, Message};
-
-mod messages;
-const FUTURE_POSITION: f32 = 0.2;
-
-use messages::Messages;
+use nannou::winit::monitor::MonitorHandle;
+use nannou::winit::window::Fullscreen;
+use std::cmp::min;
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use green_graph::blend::{Blend, Edge};
+use green_graph::matrix::Matrix;
+use green_graph::messages::{self, ErrorMessage, Messages};
+use green_graph::panel::{Panel, Role};
+use green_graph::sync::SyncChange;
+use green_graph::tempo::{self, Tempo};
+use green_graph::timeline::{Rejected, Timeline};
+use green_graph::warp::{self, Warp};
+
+mod config;
+mod connection;
+mod diagnostics;
+mod listen;
+mod osc_input;
+
+use config::{Args, Config, WindowConfig};
+use connection::{ConnectionEvent, ConnectionStatus, Outgoing};
+use diagnostics::Diagnostics;
+
+// widest step of an edge blend ramp, in pixels
+const BLEND_STRIP_WIDTH: f32 = 2.0;
+// how far the arrow keys move a warp corner, in pixels (ten times with shift)
+const WARP_NUDGE: f32 = 1.0;
+// how long a lost connection gets to come back before the demo takes over
+const DEMO_GRACE: Duration = Duration::from_secs(5);
+
+// read before any window opens, so bad settings fail fast
+static CONFIG: OnceLock<(Args, Config)> = OnceLock::new();
 
 fn main() {
+    CONFIG.get_or_init(load_config);
     nannou::app(model).update(update).run()
 }
 
 struct Model {
-    // Store the window ID so we can refer to this specific window later if needed.
-    // _window1: WindowId,
-    _window2: WindowId,
-    _window3: WindowId,
-    matrix: Vec<i32>,
-    buffers_left: Vec<Vec<i32>>,
-    buffers_mid: Vec<Vec<i32>>,
-    buffers_right: Vec<Vec<i32>>,
-    matrix_position: usize,
-    skipped: bool,
-    num_steps_on_screen: usize,
-    graph_offset: f32,
-    tempo: f32,
-    num_graphs: usize,
-    ws_client: websocket::sender::Writer<TcpStream>,
-    ws_receiver: Receiver<Messages>,
+    // one window per panel, in the order of `timeline.panels()`
+    windows: Vec<WindowId>,
+    // edge blending, per window
+    blends: Vec<Blend>,
+    // keystone correction, per window, kept in `warp_file`
+    warps: Vec<Warp>,
+    warp_file: PathBuf,
+    // set while the corners are being adjusted
+    warp_edit: Option<WarpEdit>,
+    // the settings in effect, to tell what a reload changes
+    config: Config,
+    watcher: config::Watcher,
+    timeline: Timeline,
+    // Both are replaced every time the connection thread (re)connects.
+    ws_client: Option<Outgoing>,
+    ws_receiver: Option<Receiver<Messages>>,
+    connection: Receiver<ConnectionEvent>,
+    // from `--osc`, next to whichever of the two above
+    osc_receiver: Option<Receiver<Messages>>,
+    // an OSC controller spoke up; like a connection, that ends the demo
+    osc_heard: bool,
+    // while we're the server or take OSC, what `/get-matrix` gets
+    served_matrix: Option<listen::SharedMatrix>,
+    connection_status: ConnectionStatus,
+    diagnostics: Arc<Mutex<Diagnostics>>,
+    // `--demo` keeps the generator running even while connected
+    demo: bool,
+    // whether the demo is what's showing right now
+    showing_demo: bool,
+    // when the server or the last controller went, while we wait for them back
+    control_lost_at: Option<Instant>,
+    // the server's or controllers' matrix, kept while the demo shows its own
+    live_matrix: Option<Matrix>,
+    // `--error-replies` also reports messages we can't apply back to the server
+    error_replies: bool,
     is_black: bool,
 }
 
+/// The corner being adjusted, in the window last clicked.
+#[derive(Debug, Clone, Copy)]
+struct WarpEdit {
+    window: usize,
+    corner: usize,
+    dragging: bool,
+}
+
 impl Model {
-    pub fn increment_num_steps_on_screen(&mut self) {
-        for b in self.buffers_left.iter_mut() {
-            b.insert(0, 0);
+    /// Whether the control server or any controller of ours is connected, or
+    /// OSC has come in.
+    fn is_controlled(&self) -> bool {
+        self.connection_status == ConnectionStatus::Connected || self.osc_heard
+    }
+
+    /// Demo mode: forced with `--demo`, or while nobody is in control, once a
+    /// lost connection has had `DEMO_GRACE` to come back.
+    pub fn is_demo(&self) -> bool {
+        let waiting = self
+            .control_lost_at
+            .is_some_and(|lost| lost.elapsed() < DEMO_GRACE);
+        self.demo || (!self.is_controlled() && !waiting)
+    }
+
+    /// The index of window `id`, which is also that of its panel.
+    fn window_index(&self, id: WindowId) -> Option<usize> {
+        self.windows.iter().position(|w| *w == id)
+    }
+
+    /// Colour of lane `row` at `step`, lit up while its cell was recently patched.
+    fn step_color(&self, row: usize, step: isize) -> Rgba {
+        let style = &self.config.style;
+        let (line, lit) = (style.line_color, style.highlight_color);
+        let h = self.timeline.highlight(row, step);
+        rgba(
+            line.red + (lit.red - line.red) * h,
+            line.green + (lit.green - line.green) * h,
+            line.blue + (lit.blue - line.blue) * h,
+            line.alpha + (lit.alpha - line.alpha) * h,
+        )
+    }
+
+    /// Logs a message we couldn't apply and, with `--error-replies`, tells the server.
+    ///
+    /// Controllers aren't told while we're the server: by the time the timeline
+    /// rejects a message, which of them sent it is lost in the merge.
+    fn reject(&self, rejected: Rejected) {
+        eprintln!("rejected {}", rejected);
+        if let (true, Some(client)) = (self.error_replies, self.ws_client.as_ref()) {
+            let reason = rejected.to_string();
+            connection::queue(client, Messages::Error(ErrorMessage { reason }));
+        }
+    }
+}
+
+fn model(app: &App) -> Model {
+    let (args, config) = CONFIG.get_or_init(load_config).clone();
+    let watcher = config::Watcher::new(args, &config);
+    // one projector per panel
+    let panels: Vec<Panel> = config.windows.iter().map(|w| w.panel()).collect();
+    let mut warps = match warp::load(&config.warp_file) {
+        Ok(warps) => warps,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            eprintln!("can't read {}: {}", config.warp_file.display(), e);
+            Vec::new()
         }
-        for b in self.buffers_mid.iter_mut() {
-            b.insert(0, 0);
+    };
+    warps.resize(panels.len(), Warp::default());
+    app.set_fullscreen_on_shortcut(true);
+    let windows = config
+        .windows
+        .iter()
+        .map(|w| build_window(app, w))
+        .collect();
+
+    app.set_loop_mode(LoopMode::RefreshSync);
+    let tempo = Tempo::new(
+        tempo::DEFAULT_BPM,
+        tempo::DEFAULT_SUBDIVISION,
+        config.tempo_ramp,
+    );
+    let mut timeline = Timeline::new(
+        config.steps_on_screen,
+        config.quantize,
+        tempo,
+        config.wheel,
+        panels,
+        config.span.clone(),
+    );
+    timeline.set_future_position(config.future_position);
+    timeline.set_lines(config.lines);
+    // we always start out disconnected, so there's something to look at right away
+    timeline.set_generating(true);
+    timeline.regenerate();
+    let diagnostics = Arc::new(Mutex::new(Diagnostics::default()));
+    let served_matrix = (config.server.listen.is_some() || config.server.osc.is_some())
+        .then(|| Arc::new(Mutex::new(timeline.matrix().clone())));
+    let (connection, ws_receiver) = match (config.server.listen, served_matrix.as_ref()) {
+        (Some(address), Some(matrix)) => {
+            let listener = listen::spawn(
+                address,
+                matrix.clone(),
+                diagnostics.clone(),
+                config.error_replies,
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("can't listen on {}: {}", address, e);
+                std::process::exit(1);
+            });
+            (listener.events, Some(listener.messages))
         }
-        for b in self.buffers_right.iter_mut() {
-            b.insert(0, 0);
+        _ => {
+            let events = connection::spawn(
+                config.server.clone(),
+                diagnostics.clone(),
+                config.error_replies,
+            );
+            (events, None)
         }
-        self.num_steps_on_screen = min(self.num_steps_on_screen + 1, 64);
+    };
+    let osc_receiver = match (config.server.osc, served_matrix.as_ref()) {
+        (Some(address), Some(matrix)) => Some(
+            osc_input::spawn(address, matrix.clone(), diagnostics.clone()).unwrap_or_else(|e| {
+                eprintln!("can't take OSC on {}: {}", address, e);
+                std::process::exit(1);
+            }),
+        ),
+        _ => None,
+    };
+
+    Model {
+        windows,
+        blends: config.blends.clone(),
+        warps,
+        warp_file: config.warp_file.clone(),
+        warp_edit: None,
+        demo: config.demo,
+        showing_demo: true,
+        control_lost_at: None,
+        live_matrix: None,
+        error_replies: config.error_replies,
+        config,
+        watcher,
+        timeline,
+        ws_client: None,
+        ws_receiver,
+        connection,
+        osc_receiver,
+        osc_heard: false,
+        served_matrix,
+        connection_status: ConnectionStatus::Disconnected,
+        diagnostics,
+        is_black: false,
     }
+}
 
-    pub fn decrement_num_steps_on_screen(&mut self) {
-        if self.num_steps_on_screen > 16 {
-            for b in self.buffers_left.iter_mut() {
-                b.remove(0);
-            }
+/// Adjusts the warp corners while editing: click or drag a corner with the
+/// mouse, Tab to pick the next one, the arrow keys to nudge it and Delete to
+/// start the window over. Returns whether `event` was used up.
+fn edit_warp(app: &App, model: &mut Model, event: &WindowEvent) -> bool {
+    let (mut edit, index) = match (model.warp_edit, model.window_index(app.window_id())) {
+        (Some(edit), Some(index)) => (edit, index),
+        _ => return false,
+    };
+    let win = match app.window(app.window_id()) {
+        Some(window) => window.rect(),
+        None => return false,
+    };
+    let to_unit = |p: Point2| ((p.x - win.left()) / win.w(), (p.y - win.bottom()) / win.h());
+    let step = if app.keys.mods.shift() {
+        WARP_NUDGE * 10.0
+    } else {
+        WARP_NUDGE
+    };
+    let used = match event {
+        WindowEvent::MousePressed(MouseButton::Left) => {
+            let (u, v) = to_unit(app.mouse.position());
+            edit.window = index;
+            edit.corner = model.warps[index].nearest_corner(u, v);
+            edit.dragging = true;
+            true
         }
-        if self.num_steps_on_screen > 16 {
-            for b in self.buffers_mid.iter_mut() {
-                b.remove(0);
+        WindowEvent::MouseReleased(MouseButton::Left) => {
+            edit.dragging = false;
+            true
+        }
+        WindowEvent::MouseMoved(p) if edit.dragging && edit.window == index => {
+            let (u, v) = to_unit(*p);
+            model.warps[index].move_corner(edit.corner, u, v);
+            true
+        }
+        WindowEvent::KeyPressed(key) => {
+            let warp = &mut model.warps[edit.window];
+            let (du, dv) = (step / win.w(), step / win.h());
+            match key {
+                Key::Tab => edit.corner = (edit.corner + 1) % 4,
+                Key::Left => warp.nudge(edit.corner, -du, 0.0),
+                Key::Right => warp.nudge(edit.corner, du, 0.0),
+                Key::Up => warp.nudge(edit.corner, 0.0, dv),
+                Key::Down => warp.nudge(edit.corner, 0.0, -dv),
+                Key::Delete => *warp = Warp::default(),
+                _ => return false,
             }
+            true
+        }
+        _ => false,
+    };
+    model.warp_edit = Some(edit);
+    used
+}
+
+/// The settings from the command line, the environment and the config file.
+/// Exits if they can't be used, rather than opening the wrong windows.
+fn load_config() -> (Args, Config) {
+    let args = Args::parse();
+    match Config::load(&args) {
+        Ok(config) => (args, config),
+        Err(e) => {
+            eprintln!("invalid config: {}", e);
+            std::process::exit(1);
         }
-        if self.num_steps_on_screen > 16 {
-            for b in self.buffers_right.iter_mut() {
-                b.remove(0);
+    }
+}
+
+/// Applies what changed in the config file, keeping the timeline where it
+/// is, and says what only a restart can change.
+fn reload_config(app: &App, model: &mut Model, mut config: Config) {
+    let old = &model.config;
+    let needs_restart = |what: &str| eprintln!("{} only changes on restart", what);
+    if config.server != old.server {
+        needs_restart("the [server] settings");
+        config.server = old.server.clone();
+    }
+    if config.error_replies != old.error_replies {
+        needs_restart("error_replies");
+        config.error_replies = old.error_replies;
+    }
+    if config.warp_file != old.warp_file {
+        needs_restart("warp_file");
+        config.warp_file = old.warp_file.clone();
+    }
+    if config.windows.len() != old.windows.len() {
+        eprintln!(
+            "{} windows in the config but {} open; the number of windows only changes on restart",
+            config.windows.len(),
+            old.windows.len()
+        );
+        config.windows = old.windows.clone();
+        // both were worked out for the new number
+        config.span = old.span.clone();
+        config.blends = old.blends.clone();
+    } else {
+        for (i, (new, old)) in config.windows.iter().zip(old.windows.iter()).enumerate() {
+            if !new.same_window(old) {
+                reconfigure_window(app, model.windows[i], new);
             }
         }
-        self.num_steps_on_screen = max(self.num_steps_on_screen - 1, 16);
+        let panels = config.windows.iter().map(|w| w.panel()).collect();
+        model.timeline.set_panels(panels);
+        if config.span != old.span {
+            model.timeline.set_span(config.span.clone());
+        }
+        model.blends = config.blends.clone();
+    }
+
+    let timeline = &mut model.timeline;
+    if config.steps_on_screen != old.steps_on_screen {
+        timeline.set_num_steps_on_screen(config.steps_on_screen);
+    }
+    if config.lines != old.lines {
+        timeline.set_lines(config.lines);
+    }
+    if config.future_position != old.future_position {
+        timeline.set_future_position(config.future_position);
+    }
+    if config.quantize != old.quantize {
+        timeline.set_quantize(config.quantize);
+    }
+    if config.wheel != old.wheel {
+        timeline.set_wheel(config.wheel);
+    }
+    if config.tempo_ramp != old.tempo_ramp {
+        timeline.set_tempo_ramp(config.tempo_ramp);
     }
+    model.demo = config.demo;
+    println!("reloaded {}", model.watcher.path().display());
+    model.config = config;
 }
 
-fn model(app: &App) -> Model {
-    // Create a new window! Store the ID so we can refer to it later.
-    // let _window1 = app
-    //     .new_window()
-    //     // .fullscreen()
-    //     .size(1920, 1080)
-    //     .title("left")
-    //     .view(view_left) // The function that will be called for presenting graphics to a frame.
-    //     .event(event) // The function that will be called when the window receives events.
-    //     .build()
-    //     .unwrap();
-    app.set_fullscreen_on_shortcut(true);
-    let _window2 = app
-        .new_window()
-        // .fullscreen()
-        .size(1920, 1080)
-        .title("mid")
-        .view(view_mid) // The function that will be called for presenting graphics to a frame.
-        .event(event) // The function that will be called when the window receives events.
-        .build()
-        .unwrap();
-
-    let _window3 = app
+/// The monitor `config` asks for, if there is one.
+fn find_monitor(app: &App, config: &WindowConfig) -> Option<MonitorHandle> {
+    let i = config.monitor?;
+    let monitor = app.available_monitors().into_iter().nth(i);
+    if monitor.is_none() {
+        eprintln!("no monitor {} for window {:?}", i, config.title());
+    }
+    monitor
+}
+
+/// Opens the window `config` describes, on its monitor if there is one.
+fn build_window(app: &App, config: &WindowConfig) -> WindowId {
+    let monitor = find_monitor(app, config);
+    let [width, height] = config.size;
+    let mut builder = app
         .new_window()
-        // .fullscreen()
-        .size(1920, 1080)
-        .title("right")
-        .view(view_right) // The function that will be called for presenting graphics to a frame.
-        .event(event) // The function that will be called when the window receives events.
-        .build()
-        .unwrap();
-
-    let num_steps_on_screen = 64;
-    app.set_loop_mode(LoopMode::RefreshSync);
-    let matrix = vec![0; 64];
-    let buffers_left: Vec<Vec<i32>> = vec![vec![0; num_steps_on_screen + 1]; 4];
-    let buffers_mid: Vec<Vec<i32>> = vec![vec![0; num_steps_on_screen + 1]; 4];
-    let buffers_right: Vec<Vec<i32>> = vec![vec![0; num_steps_on_screen + 1]; 4];
-    let ip = std::env::var("WS_SERVER_IP").unwrap_or_else(|_| String::from("127.0.0.1"));
-    let address = format!("ws://{}:8080", ip);
-    let ws_client = ClientBuilder::new(&address)
-        .unwrap()
-        .connect_insecure()
-        .unwrap();
-
-    let (mut receiver, sender) = ws_client.split().unwrap();
-    let (send, recv): (_, Receiver<Messages>) = channel();
-
-    let model = Model {
-        // _window1,
-        _window2,
-        _window3,
-        matrix,
-        buffers_left,
-        buffers_mid,
-        buffers_right,
-        matrix_position: 0,
-        skipped: true,
-        num_steps_on_screen,
-        graph_offset: 0.0,
-        tempo: 60.0,
-        num_graphs: 4,
-        ws_client: sender,
-        ws_receiver: recv,
-        is_black: false,
+        .size(width, height)
+        .title(config.title())
+        .view(view) // The function that will be called for presenting graphics to a frame.
+        .event(event); // The function that will be called when the window receives events.
+    if config.fullscreen {
+        builder = builder.fullscreen_with(Some(Fullscreen::Borderless(monitor.clone())));
+    }
+    let id = builder.build().unwrap();
+    if let Some(window) = app.window(id) {
+        place_window(&window, config, monitor);
+    }
+    id
+}
+
+/// Gives the open window `id` what `config` now describes.
+fn reconfigure_window(app: &App, id: WindowId, config: &WindowConfig) {
+    let window = match app.window(id) {
+        Some(window) => window,
+        None => return,
     };
+    let monitor = find_monitor(app, config);
+    window.set_title(config.title());
+    if config.fullscreen {
+        window.set_fullscreen_with(Some(Fullscreen::Borderless(monitor)));
+        return;
+    }
+    window.set_fullscreen_with(None);
+    let [width, height] = config.size;
+    window.set_inner_size_points(width as f32, height as f32);
+    place_window(&window, config, monitor);
+}
 
-    std::thread::spawn(move || {
-        for message in receiver.incoming_messages() {
-            if let Some(m) = message.ok() {
-                // println!("Recv: {:?}", m);
-                match m {
-                    OwnedMessage::Text(msg) => {
-                        let maybe_server_msg: Option<messages::ServerMessage> =
-                            serde_json::from_str(&msg).ok();
-                        if let Some(server_msg) = maybe_server_msg {
-                            if server_msg.addr == "/matrix" {
-                                let internal_msg: Option<messages::MatrixMessage> =
-                                    serde_json::from_str(&msg).ok();
-                                if let Some(internal_msg) = internal_msg {
-                                    send.send(Messages::Matrix(internal_msg)).unwrap();
-                                }
-                            } else if server_msg.addr == "/wheel" {
-                                let internal_msg: Option<messages::WheelMessage> =
-                                    serde_json::from_str(&msg).ok();
-                                if let Some(internal_msg) = internal_msg {
-                                    send.send(Messages::Wheel(internal_msg)).unwrap();
-                                }
-                            } else if server_msg.addr == "/lines" {
-                                let internal_msg: Option<messages::LinesMessage> =
-                                    serde_json::from_str(&msg).ok();
-                                if let Some(internal_msg) = internal_msg {
-                                    send.send(Messages::Lines(internal_msg)).unwrap();
-                                }
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        }
-    });
-    model
+/// Moves a window that isn't fullscreen to its position on `monitor`.
+fn place_window(window: &Window, config: &WindowConfig, monitor: Option<MonitorHandle>) {
+    if config.fullscreen || (monitor.is_none() && config.position.is_none()) {
+        return;
+    }
+    let origin = monitor.map(|m| m.position()).unwrap_or_default();
+    let [x, y] = config.position.unwrap_or_default();
+    window.set_outer_position_pixels(origin.x + x, origin.y + y);
 }
 
 // Handle events related to the window and update the model if necessary
 fn event(app: &App, model: &mut Model, event: WindowEvent) {
+    if edit_warp(app, model, &event) {
+        return;
+    }
     match event {
-        // generate random matrix on mouse press
-        // WindowEvent::MousePressed(_) => {
-        //     let mut rng = rand::thread_rng();
-        //     model.matrix = model.matrix.iter().map(|_| rng.gen_range(0..2)).collect();
-        //     dbg!(&model.matrix);
-        // }
+        // generate a new demo pattern on mouse press
+        WindowEvent::MousePressed(_) if model.is_demo() => {
+            model.timeline.regenerate();
+        }
         WindowEvent::KeyPressed(key) => match key {
             Key::Left => {
-                model.increment_num_steps_on_screen();
-                dbg!(model.num_steps_on_screen);
+                model.timeline.increment_num_steps_on_screen();
+                dbg!(model.timeline.num_steps_on_screen());
             }
             Key::Right => {
-                model.decrement_num_steps_on_screen();
-                dbg!(model.num_steps_on_screen);
+                model.timeline.decrement_num_steps_on_screen();
+                dbg!(model.timeline.num_steps_on_screen());
             }
             Key::F => {
                 app.main_window().set_fullscreen(true);
@@ -201,22 +456,69 @@ fn event(app: &App, model: &mut Model, event: WindowEvent) {
                 model.is_black = !model.is_black;
             }
             Key::Key1 => {
-                model.num_graphs = 1;
+                model.timeline.set_lines(1);
             }
             Key::Key2 => {
-                model.num_graphs = 2;
+                model.timeline.set_lines(2);
             }
             Key::Key3 => {
-                model.num_graphs = 3;
+                model.timeline.set_lines(3);
             }
             Key::Key4 => {
-                model.num_graphs = 4;
+                model.timeline.set_lines(4);
             }
-            Key::S => {
-                let matrix_request = messages::MatrixRequestMessage::new();
-                let mr_json = serde_json::to_string(&matrix_request).unwrap();
-                let m = Message::text(&mr_json);
-                model.ws_client.send_message(&m).unwrap();
+            Key::G => {
+                let algorithm = model.timeline.next_algorithm();
+                println!("demo pattern: {}", algorithm.name());
+                if model.is_demo() {
+                    model.timeline.regenerate();
+                }
+            }
+            Key::P => {
+                let transport = model.timeline.transport().toggled();
+                model.timeline.set_transport(transport);
+                println!("transport: {:?}", transport);
+            }
+            Key::Home => {
+                model.timeline.reset();
+            }
+            Key::Comma => {
+                model.timeline.seek_back();
+            }
+            Key::Period => {
+                model.timeline.seek(model.timeline.position() + 1);
+            }
+            Key::Q => {
+                let quantize = model.timeline.quantize().next();
+                model.timeline.set_quantize(quantize);
+                println!("quantize: {}", quantize.name());
+            }
+            Key::S => match model.ws_client.as_ref() {
+                Some(client) => connection::queue(client, Messages::GetMatrix),
+                None => println!("not connected, can't request matrix"),
+            },
+            Key::W => match model.warp_edit.take() {
+                Some(_) => match warp::save(&model.warp_file, &model.warps) {
+                    Ok(()) => println!("warp saved to {}", model.warp_file.display()),
+                    Err(e) => eprintln!("can't save {}: {}", model.warp_file.display(), e),
+                },
+                None => {
+                    println!("editing warp: drag the corners, or Tab and the arrow keys");
+                    model.warp_edit = Some(WarpEdit {
+                        window: model.window_index(app.window_id()).unwrap_or(0),
+                        corner: 0,
+                        dragging: false,
+                    });
+                }
+            },
+            Key::D => {
+                println!(
+                    "at beat {:.2}, step {}, {:.1} BPM",
+                    model.timeline.clock().beats(),
+                    model.timeline.position(),
+                    model.timeline.tempo().bpm()
+                );
+                model.diagnostics.lock().unwrap().print();
             }
             _ => (),
         },
@@ -225,337 +527,447 @@ fn event(app: &App, model: &mut Model, event: WindowEvent) {
 }
 
 fn update(app: &App, model: &mut Model, _update: Update) {
-    if let Some(m) = model.ws_receiver.try_recv().ok() {
-        match m {
-            Messages::Matrix(m) => {
-                model.matrix = m.matrix;
-            }
-            Messages::Wheel(m) => {
-                model.tempo = m.value as f32 / 8.0;
+    match model.watcher.poll() {
+        Some(Ok(config)) => reload_config(app, model, config),
+        // keep going with what we have, it's probably a half-finished edit
+        Some(Err(e)) => eprintln!("not reloading the config: {}", e),
+        None => (),
+    }
+
+    let was_controlled = model.is_controlled();
+    for event in model.connection.try_iter() {
+        match event {
+            ConnectionEvent::Status(status) => {
+                if status != ConnectionStatus::Connected {
+                    model.ws_client = None;
+                }
+                model.connection_status = status;
             }
-            Messages::Lines(m) => {
-                model.num_graphs = m.value;
+            ConnectionEvent::Connected(outgoing, receiver) => {
+                // whatever we showed meanwhile, the server's pattern is the one
+                connection::queue(&outgoing, Messages::GetMatrix);
+                model.ws_client = Some(outgoing);
+                model.ws_receiver = Some(receiver);
             }
         }
     }
 
-    let win = app.window_rect();
-    let step_size = win.w() / model.num_steps_on_screen as f32;
-    let t = app.duration.since_prev_update.as_secs_f32();
-    let old_offset = model.graph_offset;
-    // let tempo = model.tempo;
-    model.graph_offset = (model.graph_offset + model.tempo * t * 10.0) % step_size;
-    // let offset = model.graph_offset;
-    if old_offset > model.graph_offset {
-        let matrix_cycle_len = model.matrix.len() / 2;
-        model.matrix_position = (model.matrix_position + 1) % matrix_cycle_len;
-        let now_steps = (model.num_steps_on_screen as f32 * FUTURE_POSITION) as usize;
-        for (i, b) in model.buffers_left.iter_mut().enumerate() {
-            if i < model.num_graphs {
-                // hier kommen die werte vom mittleren buffer an
-                b.remove(0);
-                b.push(model.buffers_mid[i][0]);
-            }
+    // apply everything that arrived since the last frame, not just one message
+    let mut received: Vec<Messages> = match model.ws_receiver.as_ref() {
+        Some(receiver) => receiver.try_iter().collect(),
+        None => Vec::new(),
+    };
+    if let Some(receiver) = model.osc_receiver.as_ref() {
+        let before = received.len();
+        received.extend(receiver.try_iter());
+        model.osc_heard |= received.len() > before;
+    }
+
+    match (was_controlled, model.is_controlled()) {
+        (true, false) => {
+            println!(
+                "control server gone, switching to demo mode in {}s unless it's back",
+                DEMO_GRACE.as_secs()
+            );
+            model.control_lost_at = Some(Instant::now());
         }
-        for (i, b) in model.buffers_mid.iter_mut().enumerate() {
-            if i < model.num_graphs {
-                // hier müssen die aktuellen werte der Matrix, rückwärtsgehend vom nächsten Wert direkt in den buffer geschrieben werden
-                for n in 1..now_steps {
-                    let offset = (model.matrix_position - n) % matrix_cycle_len;
-                    let value = model.matrix[offset + (i * matrix_cycle_len)];
-                    b[model.num_steps_on_screen - (n - 1)] = value;
-                }
-                b.remove(0);
-                b.push(model.matrix[model.matrix_position + (i * matrix_cycle_len)]);
-            }
+        (false, true) => model.control_lost_at = None,
+        _ => (),
+    }
+    let demo = model.is_demo();
+    if demo && !model.showing_demo {
+        println!("switching to demo mode");
+        model.live_matrix = Some(model.timeline.matrix().clone());
+        model.timeline.regenerate();
+    } else if !demo && model.showing_demo {
+        if let Some(matrix) = model.live_matrix.take() {
+            println!("back in control, restoring the last pattern");
+            model.timeline.set_matrix(matrix);
         }
-        for (i, b) in model.buffers_right.iter_mut().enumerate() {
-            if i < model.num_graphs {
-                // hier müssten die aktuellen werte der Matrix, rückwärtsgehend vom nächsten Wert direkt in den buffer geschrieben werden
-                for n in 1..model.num_steps_on_screen {
-                    let offset = (model.matrix_position - n) % matrix_cycle_len;
-                    let value = model.matrix[offset + (i * matrix_cycle_len)];
-                    b[model.num_steps_on_screen - (n - 1)] = value;
-                }
-                b.remove(0);
-                b.push(model.matrix[model.matrix_position + (i * matrix_cycle_len)]);
+    }
+    model.showing_demo = demo;
+    model.timeline.set_generating(demo);
+
+    for m in messages::coalesce(received) {
+        if let Err(rejected) = model.timeline.apply(m) {
+            model.reject(rejected);
+        }
+    }
+
+    let advance = model
+        .timeline
+        .advance(app.duration.since_prev_update.as_secs_f32());
+    match advance.sync {
+        Some(SyncChange::Following) => println!("following the server clock"),
+        Some(SyncChange::Lost) => println!("server clock stopped, running on our own tempo"),
+        None => (),
+    }
+
+    if let Some(served) = model.served_matrix.as_ref() {
+        let mut served = served.lock().unwrap();
+        if *served != *model.timeline.matrix() {
+            *served = model.timeline.matrix().clone();
+        }
+    }
+}
+
+/// Vertical placement of the lanes in a window.
+///
+/// Lanes start at 20% above the centre (moved by `y_shift`) and are 30% of the
+/// window height apart; when there are too many to fit, they move closer together.
+struct Lanes {
+    count: usize,
+    top: f32,
+    spacing: f32,
+    rect_height: f32,
+}
+
+impl Lanes {
+    fn new(win_height: f32, count: usize, y_shift: f32) -> Self {
+        let top = win_height * 0.2 + y_shift;
+        let bottom = win_height * -0.45;
+        let spacing = if count > 1 {
+            (win_height * 0.3).min((top - bottom) / (count - 1) as f32)
+        } else {
+            win_height * 0.3
+        };
+        Self {
+            count,
+            top,
+            spacing,
+            rect_height: (win_height * 0.1).min(spacing * 0.5),
+        }
+    }
+
+    fn baseline(&self, n: usize) -> f32 {
+        self.top - self.spacing * n as f32
+    }
+}
+
+/// Where a lane goes on screen: slot `i` starts at `x_start + i * step_size`,
+/// high values sit `rect_height` above `y_baseline`.
+struct LaneGeometry {
+    x_start: f32,
+    step_size: f32,
+    y_baseline: f32,
+    rect_height: f32,
+    line_weight: f32,
+}
+
+/// Draws `(slot, value)` pairs as a square wave, coloured slot by slot.
+fn draw_lane<I, C>(painter: &Painter, g: &LaneGeometry, values: I, color: C)
+where
+    I: IntoIterator<Item = (usize, i32)>,
+    C: Fn(usize) -> Rgba,
+{
+    let mut prev = 0;
+    for (i, v) in values {
+        let color = color(i);
+        let x = g.x_start + g.step_size * i as f32;
+        let next_x = x + g.step_size;
+        if v == 1 {
+            let y_offset = g.y_baseline + g.rect_height;
+            if prev == 0 {
+                painter.line(
+                    pt2(x, g.y_baseline),
+                    pt2(x, y_offset + (g.line_weight * 0.5)),
+                    g.line_weight,
+                    color,
+                );
+            }
+            painter.line(
+                pt2(x, y_offset),
+                pt2(next_x, y_offset),
+                g.line_weight,
+                color,
+            );
+        } else {
+            if prev == 1 {
+                painter.line(
+                    pt2(x, g.y_baseline + g.rect_height + (g.line_weight * 0.5)),
+                    pt2(x, g.y_baseline + g.line_weight * -0.5),
+                    g.line_weight,
+                    color,
+                );
             }
+            painter.line(
+                pt2(x, g.y_baseline),
+                pt2(next_x, g.y_baseline),
+                g.line_weight,
+                color,
+            );
         }
-        model.skipped = true
-    } else {
-        model.skipped = false
+        prev = v;
     }
 }
 
-fn view_left(app: &App, model: &Model, frame: Frame) {
-    // Begin drawing
-    let draw = app.draw();
+/// Draws in window coordinates, pre-distorted by the window's warp so it
+/// comes out rectangular on the wall. Nothing is drawn outside the window.
+struct Painter<'a> {
+    draw: &'a Draw,
+    warp: &'a Warp,
+    win: Rect,
+}
 
-    // Clear the background to black.
-    draw.background().color(BLACK);
+impl Painter<'_> {
+    fn map(&self, p: Point2) -> Point2 {
+        let u = (p.x - self.win.left()) / self.win.w();
+        let v = (p.y - self.win.bottom()) / self.win.h();
+        let (x, y) = self.warp.map(u, v);
+        pt2(
+            self.win.left() + x * self.win.w(),
+            self.win.bottom() + y * self.win.h(),
+        )
+    }
 
-    if !model.is_black {
-        let win = app.window_rect();
-        let win_width = win.w();
-        let win_height = win.h();
-        let step_size = win_width / model.num_steps_on_screen as f32;
-
-        let rect_height = win_height * 0.1;
-        let mut prev = 0;
-        let offset = -1.0 * model.graph_offset;
-        const line_weight: f32 = 4.0;
-        let x_offset = win_width * -0.5;
-
-        // Draw the line!
-        for (n, b) in model.buffers_left.iter().enumerate() {
-            if n < model.num_graphs {
-                let y_baseline = (win_height * 0.2) - (win_height * 0.3 * n as f32) - 200.0;
-                for (i, v) in b.iter().enumerate() {
-                    if *v == 1 {
-                        let current_step = step_size * i as f32;
-                        let next_step = step_size * (i + 1) as f32;
-                        let y_offset = y_baseline + rect_height;
-                        if prev == 0 {
-                            draw.line()
-                                .weight(line_weight)
-                                .color(GREEN)
-                                .start(geom::point::pt2(
-                                    x_offset + offset + current_step,
-                                    y_baseline,
-                                ))
-                                .end(geom::point::pt2(
-                                    x_offset + offset + current_step,
-                                    y_offset + (line_weight * 0.5),
-                                ));
-                        }
-                        draw.line()
-                            .weight(line_weight)
-                            .color(GREEN)
-                            .start(geom::point::pt2(x_offset + offset + current_step, y_offset))
-                            .end(geom::point::pt2(x_offset + offset + next_step, y_offset));
-                    } else {
-                        if prev == 1 {
-                            draw.line().weight(line_weight).color(GREEN).points(
-                                geom::point::pt2(
-                                    x_offset + offset + (step_size * i as f32),
-                                    y_baseline + rect_height + (line_weight * 0.5),
-                                ),
-                                geom::point::pt2(
-                                    x_offset + offset + (step_size * i as f32),
-                                    y_baseline + line_weight * -0.5,
-                                ),
-                            );
-                        }
-                        draw.line().weight(line_weight).color(GREEN).points(
-                            geom::point::pt2(
-                                x_offset + offset + (step_size * i as f32),
-                                y_baseline,
-                            ),
-                            geom::point::pt2(
-                                x_offset + offset + (step_size * (i + 1) as f32),
-                                y_baseline,
-                            ),
-                        );
-                    }
-                    prev = *v;
-                }
+    /// Straight lines stay straight under the warp, so mapping the ends is enough.
+    fn line(&self, start: Point2, end: Point2, weight: f32, color: Rgba) {
+        if let Some((start, end)) = clip(self.win, start, end) {
+            self.draw
+                .line()
+                .weight(weight)
+                .color(color)
+                .start(self.map(start))
+                .end(self.map(end));
+        }
+    }
+
+    fn rect(&self, rect: Rect, color: Rgba) {
+        if let Some(r) = rect.overlap(self.win) {
+            let corners = [
+                r.bottom_left(),
+                r.bottom_right(),
+                r.top_right(),
+                r.top_left(),
+            ];
+            let [a, b, c, d] = corners;
+            self.draw
+                .quad()
+                .points(self.map(a), self.map(b), self.map(c), self.map(d))
+                .color(color);
+        }
+    }
+}
+
+/// The part of the line from `a` to `b` inside `win`, if any.
+fn clip(win: Rect, a: Point2, b: Point2) -> Option<(Point2, Point2)> {
+    let d = b - a;
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    let bounds = [
+        (-d.x, a.x - win.left()),
+        (d.x, win.right() - a.x),
+        (-d.y, a.y - win.bottom()),
+        (d.y, win.top() - a.y),
+    ];
+    for &(p, q) in bounds.iter() {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
             }
+            continue;
+        }
+        let t = q / p;
+        if p < 0.0 {
+            t0 = t0.max(t);
+        } else {
+            t1 = t1.min(t);
+        }
+        if t0 > t1 {
+            return None;
         }
     }
-    // Write the result of our drawing to the window's frame.
-    draw.to_frame(app, &frame).unwrap();
+    Some((a + d * t0, a + d * t1))
 }
 
-fn view_mid(app: &App, model: &Model, frame: Frame) {
+fn view(app: &App, model: &Model, frame: Frame) {
     // Begin drawing
     let draw = app.draw();
 
     // Clear the background to black.
     draw.background().color(BLACK);
-    if !model.is_black {
-        let win = app.window_rect();
-        let win_width = win.w();
-        let win_height = win.h();
-        let half_win_height = win_height * 0.5;
-        let step_size = win_width / model.num_steps_on_screen as f32;
-
-        let rect_height = win_height * 0.1;
-        let mut prev = 0;
-        let offset = -1.0 * model.graph_offset;
-        const line_weight: f32 = 3.0;
-        let x_offset = win_width * -0.5;
-
-        // Draw the line!
-        for (n, b) in model.buffers_mid.iter().enumerate() {
-            if n < model.num_graphs {
-                let y_baseline = (win_height * 0.2) - (win_height * 0.3 * n as f32);
-                for (i, v) in b.iter().enumerate() {
-                    if *v == 1 {
-                        let current_step = step_size * i as f32;
-                        let next_step = step_size * (i + 1) as f32;
-                        let y_offset = y_baseline + rect_height;
-                        if prev == 0 {
-                            draw.line()
-                                .weight(line_weight)
-                                .color(GREEN)
-                                .start(geom::point::pt2(
-                                    x_offset + offset + current_step,
-                                    y_baseline,
-                                ))
-                                .end(geom::point::pt2(
-                                    x_offset + offset + current_step,
-                                    y_offset + (line_weight * 0.5),
-                                ));
-                        }
-                        draw.line()
-                            .weight(line_weight)
-                            .color(GREEN)
-                            .start(geom::point::pt2(x_offset + offset + current_step, y_offset))
-                            .end(geom::point::pt2(x_offset + offset + next_step, y_offset));
-                    } else {
-                        if prev == 1 {
-                            draw.line()
-                                .weight(line_weight)
-                                .color(GREEN)
-                                .start(geom::point::pt2(
-                                    x_offset + offset + (step_size * i as f32),
-                                    y_baseline + rect_height + (line_weight * 0.5),
-                                ))
-                                .end(geom::point::pt2(
-                                    x_offset + offset + (step_size * i as f32),
-                                    y_baseline + line_weight * -0.5,
-                                ));
-                        }
-                        draw.line()
-                            .weight(line_weight)
-                            .color(GREEN)
-                            .start(geom::point::pt2(
-                                x_offset + offset + (step_size * i as f32),
-                                y_baseline,
-                            ))
-                            .end(geom::point::pt2(
-                                x_offset + offset + (step_size * (i + 1) as f32),
-                                y_baseline,
-                            ));
-                    }
-                    prev = *v;
-                }
-            }
+    if let Some(i) = model.window_index(frame.window_id()) {
+        let painter = Painter {
+            draw: &draw,
+            warp: &model.warps[i],
+            win: frame.rect(),
+        };
+        if !model.is_black {
+            view_panel(&painter, model, i);
         }
-
-        let now_steps = ((model.num_steps_on_screen as f32 * FUTURE_POSITION) as usize) as f32;
-        let future_width = now_steps * step_size;
-        let future_x_position = win.right() - future_width;
-
-        // cover the right edge of the lines with an opaque rectangle
-        draw.rect()
-            .w_h(future_width, win_height)
-            .x_y(future_x_position + (future_width * 0.5), 0.0)
-            .rgba(0.0, 0.0, 0.0, 0.7);
-
-        // mark the moment with a dashed line
-        let num_dashes = 64;
-        let dash_length = win_height / (num_dashes * 2) as f32;
-        let double_dash_length = dash_length * 2.0;
-
-        for i in 0..num_dashes {
-            let current_dash = i as f32 * double_dash_length;
-            draw.line()
-                .color(GREEN)
-                .weight(1.0)
-                .start(geom::point::pt2(
-                    future_x_position,
-                    half_win_height - current_dash,
-                ))
-                .end(geom::point::pt2(
-                    future_x_position,
-                    half_win_height - current_dash - dash_length,
-                ));
+        match model.warp_edit {
+            Some(edit) if edit.window == i => draw_warp_handles(&painter, edit.corner),
+            _ => (),
         }
+        draw_blend(&draw, &model.blends[i], frame.rect());
     }
 
     // Write the result of our drawing to the window's frame.
     draw.to_frame(app, &frame).unwrap();
 }
 
-fn view_right(app: &App, model: &Model, frame: Frame) {
-    // Begin drawing
-    let draw = app.draw();
+/// Fades out the edges where the window's projection overlaps a neighbour's,
+/// and lifts the black everywhere else to match the overlaps.
+fn draw_blend(draw: &Draw, blend: &Blend, win: Rect) {
+    // the part no other projector reaches
+    let inner = Rect::from_corners(
+        pt2(
+            win.left() + blend.overlap(Edge::Left),
+            win.bottom() + blend.overlap(Edge::Bottom),
+        ),
+        pt2(
+            win.right() - blend.overlap(Edge::Right),
+            win.top() - blend.overlap(Edge::Top),
+        ),
+    );
+    let lift = blend.black_lift;
+    if lift > 0.0 && inner.w() > 0.0 && inner.h() > 0.0 {
+        draw.blend(BLEND_ADD)
+            .rect()
+            .xy(inner.xy())
+            .wh(inner.wh())
+            .rgb(lift, lift, lift);
+    }
 
-    // Clear the background to black.
-    draw.background().color(BLACK);
-    if !model.is_black {
-        let win = app.window_rect();
-        let win_width = win.w();
-        let win_height = win.h();
-        let half_win_height = win_height * 0.5;
-        let step_size = win_width / model.num_steps_on_screen as f32;
-
-        let rect_height = win_height * 0.1;
-        let mut prev = 0;
-        let offset = -1.0 * model.graph_offset;
-        const line_weight: f32 = 4.0;
-        let x_offset = win_width * -0.5;
-
-        // Draw the line!
-        for (n, b) in model.buffers_right.iter().enumerate() {
-            if n < model.num_graphs {
-                let y_baseline = (win_height * 0.2) - (win_height * 0.3 * n as f32) - 200.0;
-                for (i, v) in b.iter().enumerate() {
-                    if *v == 1 {
-                        let current_step = step_size * i as f32;
-                        let next_step = step_size * (i + 1) as f32;
-                        let y_offset = y_baseline + rect_height;
-                        if prev == 0 {
-                            draw.line().weight(line_weight).color(GREEN).points(
-                                geom::point::pt2(x_offset + offset + current_step, y_baseline),
-                                geom::point::pt2(
-                                    x_offset + offset + current_step,
-                                    y_offset + (line_weight * 0.5),
-                                ),
-                            );
-                        }
-                        draw.line().weight(line_weight).color(GREEN).points(
-                            geom::point::pt2(x_offset + offset + current_step, y_offset),
-                            geom::point::pt2(x_offset + offset + next_step, y_offset),
-                        );
-                    } else {
-                        if prev == 1 {
-                            draw.line().weight(line_weight).color(GREEN).points(
-                                geom::point::pt2(
-                                    x_offset + offset + (step_size * i as f32),
-                                    y_baseline + rect_height + (line_weight * 0.5),
-                                ),
-                                geom::point::pt2(
-                                    x_offset + offset + (step_size * i as f32),
-                                    y_baseline + line_weight * -0.5,
-                                ),
-                            );
-                        }
-                        draw.line().weight(line_weight).color(GREEN).points(
-                            geom::point::pt2(
-                                x_offset + offset + (step_size * i as f32),
-                                y_baseline,
-                            ),
-                            geom::point::pt2(
-                                x_offset + offset + (step_size * (i + 1) as f32),
-                                y_baseline,
-                            ),
-                        );
-                    }
-                    prev = *v;
-                }
-            }
+    for edge in Edge::ALL.iter().copied() {
+        let e = match blend.edge(edge) {
+            Some(e) => e,
+            None => continue,
+        };
+        // thin enough strips that the ramp looks smooth
+        let strips = (e.width / BLEND_STRIP_WIDTH).ceil().max(1.0) as usize;
+        let strip = e.width / strips as f32;
+        for i in 0..strips {
+            // distance of the strip's centre from the edge
+            let d = (i as f32 + 0.5) * strip;
+            let (x, y, w, h) = match edge {
+                Edge::Left => (win.left() + d, win.y(), strip, win.h()),
+                Edge::Right => (win.right() - d, win.y(), strip, win.h()),
+                Edge::Top => (win.x(), win.top() - d, win.w(), strip),
+                Edge::Bottom => (win.x(), win.bottom() + d, win.w(), strip),
+            };
+            draw.rect()
+                .x_y(x, y)
+                .w_h(w, h)
+                .rgba(0.0, 0.0, 0.0, 1.0 - e.gain(d / e.width));
+        }
+    }
+}
+
+/// Outlines the warped window and marks its corners, `selected` the brightest.
+fn draw_warp_handles(painter: &Painter, selected: usize) {
+    let white = rgba(1.0, 1.0, 1.0, 1.0);
+    let win = painter.win;
+    let corners = [
+        win.bottom_left(),
+        win.bottom_right(),
+        win.top_right(),
+        win.top_left(),
+    ];
+    for (i, &corner) in corners.iter().enumerate() {
+        let next = corners[(i + 1) % corners.len()];
+        painter
+            .draw
+            .line()
+            .weight(2.0)
+            .color(white)
+            .start(painter.map(corner))
+            .end(painter.map(next));
+        let radius = if i == selected { 16.0 } else { 8.0 };
+        painter
+            .draw
+            .ellipse()
+            .xy(painter.map(corner))
+            .radius(radius)
+            .color(white);
+    }
+}
+
+/// Draws the lanes as the panel of window `index` sees them, dimming everything
+/// that hasn't been played yet and marking the moment with a dashed line.
+fn view_panel(painter: &Painter, model: &Model, index: usize) {
+    let win = painter.win;
+    let timeline = &model.timeline;
+    let window = match timeline.window(index) {
+        Some(window) => window,
+        None => return,
+    };
+    let step_size = win.w() / window.steps;
+    // slot `i` shows step `first + i`; one more than fits, as they scroll left
+    let first = window.first.floor() as isize;
+    let last = (window.first + window.steps).ceil() as isize;
+    let slots = (first..=last).enumerate();
+    // lines only run on across the seams if every window draws them alike
+    let role = timeline.panels()[index].role;
+    let (y_shift, line_weight) = if timeline.is_spanning() || role == Role::Now {
+        (0.0, model.config.style.line_weight)
+    } else {
+        (-200.0, model.config.style.side_line_weight)
+    };
+    let lanes = Lanes::new(win.h(), timeline.lanes(), y_shift);
+    let phase = timeline.clock().phase();
+    let x_start = win.left() + (first as f32 - window.first - phase) * step_size;
+
+    // Draw the line!
+    for n in 0..lanes.count {
+        let geometry = LaneGeometry {
+            x_start,
+            step_size,
+            y_baseline: lanes.baseline(n),
+            rect_height: lanes.rect_height,
+            line_weight,
+        };
+        let values = slots.clone().map(|(i, step)| (i, timeline.value(n, step)));
+        draw_lane(painter, &geometry, values, |i| {
+            model.step_color(n, first + i as isize)
+        });
+    }
+
+    // the current step starts here, before scrolling
+    let now_x = win.left() - window.first * step_size;
+
+    // cover what's still to come with an opaque rectangle
+    let future_x = now_x.max(win.left());
+    if future_x < win.right() {
+        let future = Rect::from_corners(pt2(future_x, win.bottom()), win.top_right());
+        painter.rect(future, rgba(0.0, 0.0, 0.0, 0.7));
+    }
 
-            // cover the right edge of the lines with an opaque rectangle
+    // preview the pattern that's about to replace the current one
+    if let Some(pending) = timeline.pending() {
+        for n in 0..min(lanes.count, pending.rows()) {
+            let geometry = LaneGeometry {
+                x_start,
+                step_size,
+                y_baseline: lanes.baseline(n),
+                rect_height: lanes.rect_height,
+                line_weight: line_weight * 0.5,
+            };
+            let values = slots
+                .clone()
+                .filter(|&(_, step)| step > 0)
+                .map(|(i, step)| (i, pending.get(n, timeline.step_col(step, pending.cols()))));
+            draw_lane(painter, &geometry, values, |_| {
+                model.config.style.preview_color
+            });
         }
-        draw.rect()
-            .w_h(win_width, win_height)
-            .x_y(0.0, 0.0)
-            .rgba(0.0, 0.0, 0.0, 0.7);
-            
     }
 
-    // Write the result of our drawing to the window's frame.
-    draw.to_frame(app, &frame).unwrap();
+    // mark the moment with a dashed line
+    if now_x < win.left() || now_x > win.right() {
+        return;
+    }
+    let num_dashes = 64;
+    let dash_length = win.h() / (num_dashes * 2) as f32;
+    let double_dash_length = dash_length * 2.0;
+    let color = model.config.style.line_color;
+
+    for i in 0..num_dashes {
+        let current_dash = i as f32 * double_dash_length;
+        painter.line(
+            pt2(now_x, win.top() - current_dash),
+            pt2(now_x, win.top() - current_dash - dash_length),
+            1.0,
+            color,
+        );
+    }
 }
@@ -0,0 +1,166 @@
+use std::fmt;
+
+/// The most lanes a matrix can have; each one keeps its own history.
+pub const MAX_ROWS: usize = 256;
+
+/// A pattern of `rows` lanes, each `cols` steps long, stored row after row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    cells: Vec<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShapeError {
+    /// A matrix needs at least one row and one step.
+    Empty { rows: usize, cols: usize },
+    /// More than `MAX_ROWS` lanes.
+    TooManyRows { rows: usize },
+    /// The number of cells doesn't match `rows * cols`.
+    Mismatch {
+        rows: usize,
+        cols: usize,
+        len: usize,
+    },
+    /// A patch addresses a cell outside the matrix.
+    OutOfBounds {
+        row: usize,
+        col: usize,
+        rows: usize,
+        cols: usize,
+    },
+    /// A replacement row doesn't have one value per step.
+    RowLength { cols: usize, len: usize },
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShapeError::Empty { rows, cols } => {
+                write!(f, "{}x{} matrix has no cells", rows, cols)
+            }
+            ShapeError::TooManyRows { rows } => {
+                write!(f, "{} rows, at most {} allowed", rows, MAX_ROWS)
+            }
+            ShapeError::Mismatch { rows, cols, len } => match rows.checked_mul(*cols) {
+                Some(cells) => write!(
+                    f,
+                    "{}x{} matrix needs {} cells, got {}",
+                    rows, cols, cells, len
+                ),
+                None => write!(
+                    f,
+                    "{}x{} matrix is too large, got {} cells",
+                    rows, cols, len
+                ),
+            },
+            ShapeError::OutOfBounds {
+                row,
+                col,
+                rows,
+                cols,
+            } => write!(
+                f,
+                "cell ({}, {}) is outside the {}x{} matrix",
+                row, col, rows, cols
+            ),
+            ShapeError::RowLength { cols, len } => {
+                write!(f, "row needs {} values, got {}", cols, len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ShapeError {}
+
+impl Matrix {
+    pub fn new(rows: usize, cols: usize, cells: Vec<i32>) -> Result<Self, ShapeError> {
+        if rows == 0 || cols == 0 {
+            return Err(ShapeError::Empty { rows, cols });
+        }
+        if rows > MAX_ROWS {
+            return Err(ShapeError::TooManyRows { rows });
+        }
+        if rows.checked_mul(cols) != Some(cells.len()) {
+            return Err(ShapeError::Mismatch {
+                rows,
+                cols,
+                len: cells.len(),
+            });
+        }
+        Ok(Self { rows, cols, cells })
+    }
+
+    /// An all-zero matrix; `rows` and `cols` must make a valid shape, which is
+    /// why this is only for shapes the crate picks itself.
+    pub(crate) fn zeros(rows: usize, cols: usize) -> Self {
+        debug_assert!(rows > 0 && rows <= MAX_ROWS && cols > 0);
+        Self {
+            rows,
+            cols,
+            cells: vec![0; rows * cols],
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Number of steps in one cycle.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn cells(&self) -> &[i32] {
+        &self.cells
+    }
+
+    /// The value of `row` at `step`, wrapping `step` around the cycle.
+    pub fn get(&self, row: usize, step: usize) -> i32 {
+        self.cells[row * self.cols + step % self.cols]
+    }
+
+    /// The step `behind` steps before `step`, wrapping back past the start of
+    /// the cycle as often as needed.
+    pub fn step_before(&self, step: usize, behind: usize) -> usize {
+        (step % self.cols + self.cols - behind % self.cols) % self.cols
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, value: i32) -> Result<(), ShapeError> {
+        if row >= self.rows || col >= self.cols {
+            return Err(ShapeError::OutOfBounds {
+                row,
+                col,
+                rows: self.rows,
+                cols: self.cols,
+            });
+        }
+        self.cells[row * self.cols + col] = value;
+        Ok(())
+    }
+
+    pub fn set_row(&mut self, row: usize, values: &[i32]) -> Result<(), ShapeError> {
+        if row >= self.rows {
+            return Err(ShapeError::OutOfBounds {
+                row,
+                col: 0,
+                rows: self.rows,
+                cols: self.cols,
+            });
+        }
+        if values.len() != self.cols {
+            return Err(ShapeError::RowLength {
+                cols: self.cols,
+                len: values.len(),
+            });
+        }
+        self.cells[row * self.cols..(row + 1) * self.cols].copy_from_slice(values);
+        Ok(())
+    }
+
+    /// Sets every cell to 0, keeping the shape.
+    pub fn clear(&mut self) {
+        self.cells.iter_mut().for_each(|c| *c = 0);
+    }
+}
@@ -1,44 +1,485 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashSet;
+use std::fmt;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ServerMessage {
-    pub addr: String,
-}
+use crate::matrix::Matrix;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MatrixMessage {
-    pub addr: String,
     pub matrix: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rows: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cols: Option<usize>,
+}
+
+impl MatrixMessage {
+    /// The `(rows, cols)` the server sent. If it only sent one of them, the other
+    /// follows from the length; if it sent neither, this is the old implicit
+    /// layout of two rows with `len / 2` steps each.
+    pub fn shape(&self) -> (usize, usize) {
+        let len = self.matrix.len();
+        match (self.rows, self.cols) {
+            (Some(rows), Some(cols)) => (rows, cols),
+            (Some(rows), None) => (rows, len.checked_div(rows).unwrap_or(0)),
+            (None, Some(cols)) => (len.checked_div(cols).unwrap_or(0), cols),
+            (None, None) => (2, len / 2),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl From<&Matrix> for MatrixMessage {
+    fn from(matrix: &Matrix) -> Self {
+        Self {
+            matrix: matrix.cells().to_vec(),
+            rows: Some(matrix.rows()),
+            cols: Some(matrix.cols()),
+        }
+    }
+}
+
+/// Sets a single cell.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatrixSetMessage {
+    pub row: usize,
+    pub col: usize,
+    pub value: i32,
+}
+
+/// Replaces one row; `values` needs one entry per step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatrixRowMessage {
+    pub row: usize,
+    pub values: Vec<i32>,
+}
+
+/// The server's sequencer moved to `position`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepMessage {
+    pub position: usize,
+}
+
+/// Jump to `position` in the matrix cycle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeekMessage {
+    pub position: usize,
+}
+
+/// Sets the tempo; `subdivision` is the number of steps per beat.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TempoMessage {
+    pub bpm: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subdivision: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct WheelMessage {
-    pub addr: String,
     pub value: u8,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LinesMessage {
-    pub addr: String,
     pub value: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct MatrixRequestMessage {
-    addr: String,
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorMessage {
+    pub reason: String,
 }
 
-impl MatrixRequestMessage {
-    pub fn new() -> Self {
-        Self {
-            addr: String::from("/get-matrix"),
+/// Every message exchanged with the control server, tagged by its `addr` field.
+///
+/// Payload variants go through `tracked` so a decode error names the offending field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "addr")]
+pub enum Messages {
+    #[serde(rename = "/matrix", deserialize_with = "tracked")]
+    Matrix(MatrixMessage),
+    #[serde(rename = "/matrix/set", deserialize_with = "tracked")]
+    MatrixSet(MatrixSetMessage),
+    #[serde(rename = "/matrix/row", deserialize_with = "tracked")]
+    MatrixRow(MatrixRowMessage),
+    /// Sets every cell to 0.
+    #[serde(rename = "/matrix/clear")]
+    MatrixClear,
+    /// One step of the server's clock.
+    #[serde(rename = "/clock")]
+    Clock,
+    #[serde(rename = "/step", deserialize_with = "tracked")]
+    Step(StepMessage),
+    #[serde(rename = "/transport/start")]
+    TransportStart,
+    #[serde(rename = "/transport/stop")]
+    TransportStop,
+    /// Back to the start of the cycle, with empty buffers.
+    #[serde(rename = "/transport/reset")]
+    TransportReset,
+    #[serde(rename = "/transport/seek", deserialize_with = "tracked")]
+    TransportSeek(SeekMessage),
+    #[serde(rename = "/tempo", deserialize_with = "tracked")]
+    Tempo(TempoMessage),
+    #[serde(rename = "/wheel", deserialize_with = "tracked")]
+    Wheel(WheelMessage),
+    #[serde(rename = "/lines", deserialize_with = "tracked")]
+    Lines(LinesMessage),
+    /// Asks for the current matrix: sent to the server, or by controllers to
+    /// us while we're the server.
+    #[serde(rename = "/get-matrix")]
+    GetMatrix,
+    /// Sent to the server when one of its messages couldn't be used.
+    #[serde(rename = "/error", deserialize_with = "tracked")]
+    Error(ErrorMessage),
+}
+
+impl Messages {
+    pub fn addr(&self) -> &'static str {
+        match self {
+            Messages::Matrix(_) => "/matrix",
+            Messages::MatrixSet(_) => "/matrix/set",
+            Messages::MatrixRow(_) => "/matrix/row",
+            Messages::MatrixClear => "/matrix/clear",
+            Messages::Clock => "/clock",
+            Messages::Step(_) => "/step",
+            Messages::TransportStart => "/transport/start",
+            Messages::TransportStop => "/transport/stop",
+            Messages::TransportReset => "/transport/reset",
+            Messages::TransportSeek(_) => "/transport/seek",
+            Messages::Tempo(_) => "/tempo",
+            Messages::Wheel(_) => "/wheel",
+            Messages::Lines(_) => "/lines",
+            Messages::GetMatrix => "/get-matrix",
+            Messages::Error(_) => "/error",
+        }
+    }
+
+    /// Whether a newer message with the same address makes this one redundant.
+    fn is_replaced_by_newer(&self) -> bool {
+        matches!(
+            self,
+            Messages::Tempo(_) | Messages::Wheel(_) | Messages::Lines(_)
+        )
+    }
+
+    fn edits_matrix(&self) -> bool {
+        matches!(
+            self,
+            Messages::Matrix(_)
+                | Messages::MatrixSet(_)
+                | Messages::MatrixRow(_)
+                | Messages::MatrixClear
+        )
+    }
+}
+
+/// Drops the messages in `batch` that a newer one makes redundant, so only the
+/// newest `/tempo`, `/wheel` and `/lines` are left, and nothing that edits the matrix
+/// before the newest `/matrix`. Keeps the order otherwise.
+///
+/// `/tempo` is merged rather than dropped: the one left keeps the newest
+/// `subdivision` of the batch if it didn't bring one itself.
+pub fn coalesce(batch: Vec<Messages>) -> Vec<Messages> {
+    let subdivision = batch.iter().rev().find_map(|m| match m {
+        Messages::Tempo(t) => t.subdivision,
+        _ => None,
+    });
+    let mut seen = HashSet::new();
+    let mut matrix_replaced = false;
+    let mut kept: Vec<Messages> = batch
+        .into_iter()
+        .rev()
+        .filter(|m| {
+            if m.edits_matrix() {
+                let keep = !matrix_replaced;
+                matrix_replaced |= matches!(m, Messages::Matrix(_));
+                keep
+            } else {
+                !m.is_replaced_by_newer() || seen.insert(m.addr())
+            }
+        })
+        .collect();
+    kept.reverse();
+    for m in kept.iter_mut() {
+        if let Messages::Tempo(t) = m {
+            t.subdivision = t.subdivision.or(subdivision);
         }
     }
+    kept
 }
 
 #[derive(Debug)]
-pub enum Messages {
-    Matrix(MatrixMessage),
-    Wheel(WheelMessage),
-    Lines(LinesMessage),
+pub enum Error {
+    /// The payload isn't valid JSON.
+    Syntax(serde_json::Error),
+    /// The payload is JSON, but has no string `addr` field.
+    MissingAddr,
+    /// The `addr` isn't one we know.
+    UnknownAddr(String),
+    /// The message for `addr` has a missing or mistyped field.
+    Field {
+        addr: String,
+        field: String,
+        reason: String,
+    },
+    Encode(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Syntax(e) => write!(f, "malformed JSON: {}", e),
+            Error::MissingAddr => write!(f, "message has no `addr`"),
+            Error::UnknownAddr(addr) => write!(f, "unknown address {}", addr),
+            Error::Field {
+                addr,
+                field,
+                reason,
+            } => write!(f, "{} field `{}`: {}", addr, field, reason),
+            Error::Encode(e) => write!(f, "could not encode message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Separates the field path from the reason in errors produced by `tracked`.
+const FIELD_SEPARATOR: &str = ": ";
+
+/// Deserializes a message payload while keeping track of the field path.
+///
+/// serde buffers internally tagged enums before handing them to the variant,
+/// which loses the path; tracking it here puts it back into the error.
+fn tracked<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        serde::de::Error::custom(format!("{}{}{}", path, FIELD_SEPARATOR, e.into_inner()))
+    })
+}
+
+/// Parses a message received from the server.
+pub fn decode(text: &str) -> Result<Messages, Error> {
+    let value: serde_json::Value = serde_json::from_str(text).map_err(Error::Syntax)?;
+    decode_value(value)
+}
+
+/// Turns a message already parsed as JSON, or built like one, into a `Messages`.
+pub fn decode_value(value: serde_json::Value) -> Result<Messages, Error> {
+    let addr = value
+        .get("addr")
+        .and_then(|addr| addr.as_str())
+        .ok_or(Error::MissingAddr)?
+        .to_string();
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        if e.path().to_string() == "addr" {
+            return Error::UnknownAddr(addr.clone());
+        }
+        let message = e.into_inner().to_string();
+        let (path, reason) = message
+            .split_once(FIELD_SEPARATOR)
+            .unwrap_or((".", message.as_str()));
+        // serde reports missing fields against the enclosing struct
+        let field = match reason.strip_prefix("missing field `") {
+            Some(rest) if path == "." => rest.trim_end_matches('`').to_string(),
+            _ => path.to_string(),
+        };
+        Error::Field {
+            addr,
+            field,
+            reason: reason.to_string(),
+        }
+    })
+}
+
+/// Serializes a message for sending to the server.
+pub fn encode(message: &Messages) -> Result<String, Error> {
+    serde_json::to_string(message).map_err(Error::Encode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field_error(text: &str) -> (String, String) {
+        match decode(text) {
+            Err(Error::Field { addr, field, .. }) => (addr, field),
+            other => panic!("expected a field error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn field_errors_name_the_field() {
+        assert_eq!(
+            field_error(r#"{"addr": "/matrix", "matrix": [1, 2, "3", 4]}"#),
+            (String::from("/matrix"), String::from("matrix[2]"))
+        );
+        assert_eq!(
+            field_error(r#"{"addr": "/matrix/set", "row": 0, "value": 1}"#),
+            (String::from("/matrix/set"), String::from("col"))
+        );
+    }
+
+    #[test]
+    fn addr_must_be_known_and_present() {
+        assert!(matches!(
+            decode(r#"{"addr": "/nope", "value": 1}"#),
+            Err(Error::UnknownAddr(addr)) if addr == "/nope"
+        ));
+        assert!(matches!(decode(r#"{"value": 1}"#), Err(Error::MissingAddr)));
+        assert!(matches!(decode(r#"{"addr": 7}"#), Err(Error::MissingAddr)));
+        assert!(matches!(decode("{"), Err(Error::Syntax(_))));
+    }
+
+    #[test]
+    fn encoded_messages_decode_to_themselves() {
+        for message in [
+            Messages::Matrix(MatrixMessage {
+                matrix: vec![1, 0, -1, 2],
+                rows: Some(2),
+                cols: Some(2),
+            }),
+            Messages::MatrixSet(MatrixSetMessage {
+                row: 1,
+                col: 3,
+                value: 5,
+            }),
+            Messages::MatrixRow(MatrixRowMessage {
+                row: 0,
+                values: vec![1, 2],
+            }),
+            Messages::MatrixClear,
+            Messages::Clock,
+            Messages::Step(StepMessage { position: 4 }),
+            Messages::TransportStart,
+            Messages::TransportStop,
+            Messages::TransportReset,
+            Messages::TransportSeek(SeekMessage { position: 2 }),
+            Messages::Tempo(TempoMessage {
+                bpm: 120.0,
+                subdivision: Some(4),
+            }),
+            Messages::Tempo(TempoMessage {
+                bpm: 90.5,
+                subdivision: None,
+            }),
+            Messages::Wheel(WheelMessage { value: 3 }),
+            Messages::Lines(LinesMessage { value: 2 }),
+            Messages::GetMatrix,
+            Messages::Error(ErrorMessage {
+                reason: String::from("no"),
+            }),
+        ] {
+            let text = encode(&message).unwrap();
+            assert_eq!(decode(&text).unwrap(), message, "{}", text);
+        }
+    }
+
+    fn tempo(bpm: f32, subdivision: Option<u32>) -> Messages {
+        Messages::Tempo(TempoMessage { bpm, subdivision })
+    }
+
+    fn set(value: i32) -> Messages {
+        Messages::MatrixSet(MatrixSetMessage {
+            row: 0,
+            col: 0,
+            value,
+        })
+    }
+
+    fn matrix(value: i32) -> Messages {
+        Messages::Matrix(MatrixMessage {
+            matrix: vec![value; 2],
+            rows: Some(1),
+            cols: Some(2),
+        })
+    }
+
+    #[test]
+    fn coalesce_keeps_the_newest_setting() {
+        let batch = vec![
+            Messages::Wheel(WheelMessage { value: 1 }),
+            tempo(100.0, None),
+            Messages::Lines(LinesMessage { value: 1 }),
+            Messages::Wheel(WheelMessage { value: 2 }),
+            tempo(110.0, None),
+            Messages::Lines(LinesMessage { value: 3 }),
+        ];
+        assert_eq!(
+            coalesce(batch),
+            vec![
+                Messages::Wheel(WheelMessage { value: 2 }),
+                tempo(110.0, None),
+                Messages::Lines(LinesMessage { value: 3 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn coalesce_drops_edits_before_the_newest_matrix() {
+        let batch = vec![
+            set(1),
+            matrix(1),
+            Messages::MatrixClear,
+            matrix(2),
+            set(2),
+            Messages::MatrixRow(MatrixRowMessage {
+                row: 0,
+                values: vec![3, 4],
+            }),
+        ];
+        assert_eq!(
+            coalesce(batch),
+            vec![
+                matrix(2),
+                set(2),
+                Messages::MatrixRow(MatrixRowMessage {
+                    row: 0,
+                    values: vec![3, 4],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn coalesce_merges_the_tempo_subdivision() {
+        let batch = vec![
+            tempo(100.0, Some(3)),
+            tempo(120.0, Some(4)),
+            tempo(140.0, None),
+        ];
+        assert_eq!(coalesce(batch), vec![tempo(140.0, Some(4))]);
+        let batch = vec![tempo(100.0, Some(3)), tempo(120.0, Some(2))];
+        assert_eq!(coalesce(batch), vec![tempo(120.0, Some(2))]);
+    }
+
+    #[test]
+    fn coalesce_keeps_the_order_of_everything_else() {
+        let batch = vec![
+            Messages::TransportStart,
+            tempo(100.0, None),
+            Messages::Clock,
+            set(1),
+            Messages::Step(StepMessage { position: 3 }),
+            Messages::Clock,
+            tempo(120.0, None),
+            Messages::GetMatrix,
+            Messages::TransportStop,
+        ];
+        assert_eq!(
+            coalesce(batch),
+            vec![
+                Messages::TransportStart,
+                Messages::Clock,
+                set(1),
+                Messages::Step(StepMessage { position: 3 }),
+                Messages::Clock,
+                tempo(120.0, None),
+                Messages::GetMatrix,
+                Messages::TransportStop,
+            ]
+        );
+    }
 }
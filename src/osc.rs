@@ -0,0 +1,589 @@
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+use crate::matrix::Matrix;
+use crate::messages::{self, Messages};
+
+const BUNDLE_TAG: &[u8] = b"#bundle\0";
+
+/// One argument of an OSC message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Blob(Vec<u8>),
+    Long(i64),
+    Double(f64),
+    Bool(bool),
+    /// `N` and `I`, which carry no value.
+    Nil,
+}
+
+impl Arg {
+    fn to_json(&self) -> Value {
+        match self {
+            Arg::Int(v) => json!(v),
+            Arg::Long(v) => json!(v),
+            Arg::Float(v) => float_to_json(*v as f64),
+            Arg::Double(v) => float_to_json(*v),
+            Arg::Str(v) => json!(v),
+            Arg::Blob(v) => json!(v),
+            Arg::Bool(v) => json!(v),
+            Arg::Nil => Value::Null,
+        }
+    }
+}
+
+/// Whole floats become integers, since plenty of controllers only send floats.
+fn float_to_json(v: f64) -> Value {
+    if v.fract() == 0.0 && v.abs() < i64::MAX as f64 {
+        json!(v as i64)
+    } else {
+        json!(v)
+    }
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arg::Int(v) => write!(f, "{}", v),
+            Arg::Long(v) => write!(f, "{}", v),
+            Arg::Float(v) => write!(f, "{}", v),
+            Arg::Double(v) => write!(f, "{}", v),
+            Arg::Str(v) => write!(f, "{:?}", v),
+            Arg::Blob(v) => write!(f, "<{} byte blob>", v.len()),
+            Arg::Bool(v) => write!(f, "{}", v),
+            Arg::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub addr: String,
+    pub args: Vec<Arg>,
+}
+
+/// Written like `/matrix/set 0 3 1`, for the log.
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.addr)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+impl Message {
+    /// `/matrix rows cols cells...`, the answer to `/get-matrix`.
+    pub fn matrix(matrix: &Matrix) -> Self {
+        let mut args = vec![
+            Arg::Int(matrix.rows() as i32),
+            Arg::Int(matrix.cols() as i32),
+        ];
+        args.extend(matrix.cells().iter().map(|&cell| Arg::Int(cell)));
+        Self {
+            addr: String::from("/matrix"),
+            args,
+        }
+    }
+
+    /// Maps the message onto `Messages` through the same decoding as the JSON
+    /// ones, with the arguments taking the fields in the order of `fields`.
+    pub fn to_messages(&self) -> Result<Messages, messages::Error> {
+        let mut value = Map::new();
+        value.insert(String::from("addr"), json!(self.addr));
+        let (names, rest) = match fields(&self.addr) {
+            Some(fields) => fields,
+            // let the decoding call it unknown, whatever the arguments
+            None => return messages::decode_value(Value::Object(value)),
+        };
+        let mut args = self.args.iter();
+        for name in names {
+            if let Some(arg) = args.next() {
+                value.insert(name.to_string(), arg.to_json());
+            }
+        }
+        match rest {
+            Some(name) => {
+                let list = args.map(Arg::to_json).collect();
+                value.insert(name.to_string(), Value::Array(list));
+            }
+            None => {
+                if !args.as_slice().is_empty() {
+                    return Err(messages::Error::Field {
+                        addr: self.addr.clone(),
+                        field: String::from("args"),
+                        reason: format!(
+                            "expected at most {}, got {}",
+                            names.len(),
+                            self.args.len()
+                        ),
+                    });
+                }
+            }
+        }
+        messages::decode_value(Value::Object(value))
+    }
+}
+
+/// The fields an address' arguments fill, in order, and the list field the
+/// remaining ones go to, if any.
+type Fields = (&'static [&'static str], Option<&'static str>);
+
+/// The fields of `addr`, `None` for addresses we don't know. The tests hold
+/// this against every address and field `Messages` has.
+fn fields(addr: &str) -> Option<Fields> {
+    let fields: Fields = match addr {
+        "/matrix" => (&["rows", "cols"], Some("matrix")),
+        "/matrix/set" => (&["row", "col", "value"], None),
+        "/matrix/row" => (&["row"], Some("values")),
+        "/step" | "/transport/seek" => (&["position"], None),
+        "/tempo" => (&["bpm", "subdivision"], None),
+        "/wheel" | "/lines" => (&["value"], None),
+        "/error" => (&["reason"], None),
+        "/matrix/clear" | "/clock" | "/transport/start" | "/transport/stop"
+        | "/transport/reset" | "/get-matrix" => (&[], None),
+        _ => return None,
+    };
+    Some(fields)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The packet ends in the middle of something.
+    Truncated,
+    /// A string isn't UTF-8 or isn't terminated.
+    BadString,
+    /// The address doesn't start with `/`.
+    BadAddr(String),
+    UnknownType(char),
+    /// A bundle element's size doesn't fit the bundle.
+    BadElement(i32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Truncated => write!(f, "truncated OSC packet"),
+            Error::BadString => write!(f, "malformed OSC string"),
+            Error::BadAddr(addr) => write!(f, "bad OSC address {:?}", addr),
+            Error::UnknownType(tag) => write!(f, "unknown OSC type tag `{}`", tag),
+            Error::BadElement(size) => write!(f, "bad OSC bundle element size {}", size),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Reads a packet, a single message or a bundle of them, possibly nested.
+///
+/// Bundles are taken apart in order; their time tags are ignored, so
+/// everything applies as soon as it arrives.
+pub fn decode(packet: &[u8]) -> Result<Vec<Message>, Error> {
+    let mut messages = Vec::new();
+    decode_into(packet, &mut messages)?;
+    Ok(messages)
+}
+
+fn decode_into(packet: &[u8], messages: &mut Vec<Message>) -> Result<(), Error> {
+    if !packet.starts_with(BUNDLE_TAG) {
+        messages.push(decode_message(packet)?);
+        return Ok(());
+    }
+    let mut reader = Reader {
+        bytes: packet,
+        at: BUNDLE_TAG.len(),
+    };
+    // the time tag
+    reader.take(8)?;
+    while !reader.is_done() {
+        let size = reader.int()?;
+        if size < 0 || size % 4 != 0 {
+            return Err(Error::BadElement(size));
+        }
+        let element = reader.take(size as usize)?;
+        decode_into(element, messages)?;
+    }
+    Ok(())
+}
+
+fn decode_message(packet: &[u8]) -> Result<Message, Error> {
+    let mut reader = Reader {
+        bytes: packet,
+        at: 0,
+    };
+    let addr = reader.string()?;
+    if !addr.starts_with('/') {
+        return Err(Error::BadAddr(addr));
+    }
+    let mut args = Vec::new();
+    // very old senders leave out the type tags, along with any arguments
+    if reader.is_done() {
+        return Ok(Message { addr, args });
+    }
+    let tags = reader.string()?;
+    let tags = tags.strip_prefix(',').ok_or(Error::BadString)?;
+    for tag in tags.chars() {
+        let arg = match tag {
+            'i' | 'c' | 'r' | 'm' => Arg::Int(reader.int()?),
+            'f' => Arg::Float(f32::from_bits(reader.int()? as u32)),
+            'h' | 't' => Arg::Long(reader.long()?),
+            'd' => Arg::Double(f64::from_bits(reader.long()? as u64)),
+            's' | 'S' => Arg::Str(reader.string()?),
+            'b' => Arg::Blob(reader.blob()?),
+            'T' => Arg::Bool(true),
+            'F' => Arg::Bool(false),
+            'N' | 'I' => Arg::Nil,
+            // arrays are flattened into the other arguments
+            '[' | ']' => continue,
+            tag => return Err(Error::UnknownType(tag)),
+        };
+        args.push(arg);
+    }
+    Ok(Message { addr, args })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_done(&self) -> bool {
+        self.at >= self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let end = self.at.checked_add(len).ok_or(Error::Truncated)?;
+        let bytes = self.bytes.get(self.at..end).ok_or(Error::Truncated)?;
+        self.at = end;
+        Ok(bytes)
+    }
+
+    fn int(&mut self) -> Result<i32, Error> {
+        let bytes = self.take(4)?;
+        Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn long(&mut self) -> Result<i64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(i64::from_be_bytes(bytes))
+    }
+
+    /// A NUL-terminated string, padded to a multiple of 4 bytes.
+    fn string(&mut self) -> Result<String, Error> {
+        let rest = &self.bytes[self.at.min(self.bytes.len())..];
+        let len = rest.iter().position(|&b| b == 0).ok_or(Error::BadString)?;
+        let bytes = self.take(padded(len + 1))?;
+        String::from_utf8(bytes[..len].to_vec()).map_err(|_| Error::BadString)
+    }
+
+    fn blob(&mut self) -> Result<Vec<u8>, Error> {
+        let len = self.int()?;
+        if len < 0 {
+            return Err(Error::Truncated);
+        }
+        let bytes = self.take(padded(len as usize))?;
+        Ok(bytes[..len as usize].to_vec())
+    }
+}
+
+fn padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Writes a single message as a packet.
+pub fn encode(message: &Message) -> Vec<u8> {
+    let mut packet = Vec::new();
+    write_string(&mut packet, &message.addr);
+    let tags: String = std::iter::once(',')
+        .chain(message.args.iter().map(|arg| match arg {
+            Arg::Int(_) => 'i',
+            Arg::Float(_) => 'f',
+            Arg::Str(_) => 's',
+            Arg::Blob(_) => 'b',
+            Arg::Long(_) => 'h',
+            Arg::Double(_) => 'd',
+            Arg::Bool(true) => 'T',
+            Arg::Bool(false) => 'F',
+            Arg::Nil => 'N',
+        }))
+        .collect();
+    write_string(&mut packet, &tags);
+    for arg in &message.args {
+        match arg {
+            Arg::Int(v) => packet.extend_from_slice(&v.to_be_bytes()),
+            Arg::Float(v) => packet.extend_from_slice(&v.to_be_bytes()),
+            Arg::Str(v) => write_string(&mut packet, v),
+            Arg::Blob(v) => {
+                packet.extend_from_slice(&(v.len() as i32).to_be_bytes());
+                packet.extend_from_slice(v);
+                packet.resize(padded(packet.len()), 0);
+            }
+            Arg::Long(v) => packet.extend_from_slice(&v.to_be_bytes()),
+            Arg::Double(v) => packet.extend_from_slice(&v.to_be_bytes()),
+            Arg::Bool(_) | Arg::Nil => {}
+        }
+    }
+    packet
+}
+
+fn write_string(packet: &mut Vec<u8>, s: &str) {
+    packet.extend_from_slice(s.as_bytes());
+    packet.push(0);
+    packet.resize(padded(packet.len()), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{
+        ErrorMessage, LinesMessage, MatrixMessage, MatrixRowMessage, MatrixSetMessage, SeekMessage,
+        StepMessage, TempoMessage, WheelMessage,
+    };
+
+    fn message(addr: &str, args: Vec<Arg>) -> Message {
+        Message {
+            addr: String::from(addr),
+            args,
+        }
+    }
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut packet = BUNDLE_TAG.to_vec();
+        packet.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+        for element in elements {
+            packet.extend((element.len() as i32).to_be_bytes());
+            packet.extend(element);
+        }
+        packet
+    }
+
+    #[test]
+    fn strings_are_terminated_and_padded_to_four_bytes() {
+        assert_eq!(encode(&message("/abc", vec![])), b"/abc\0\0\0\0,\0\0\0");
+        assert_eq!(
+            encode(&message("/ab", vec![Arg::Str(String::from("xyz"))])),
+            b"/ab\0,s\0\0xyz\0"
+        );
+        let blob = encode(&message("/b", vec![Arg::Blob(vec![1, 2, 3, 4, 5])]));
+        assert_eq!(&blob[8..], b"\0\0\0\x05\x01\x02\x03\x04\x05\0\0\0");
+    }
+
+    #[test]
+    fn every_type_round_trips() {
+        let sent = message(
+            "/all/types",
+            vec![
+                Arg::Int(-7),
+                Arg::Float(0.5),
+                Arg::Str(String::from("four")),
+                Arg::Str(String::new()),
+                Arg::Blob(vec![9; 6]),
+                Arg::Blob(Vec::new()),
+                Arg::Long(1 << 40),
+                Arg::Double(-2.25),
+                Arg::Bool(true),
+                Arg::Bool(false),
+                Arg::Nil,
+            ],
+        );
+        let packet = encode(&sent);
+        assert_eq!(packet.len() % 4, 0);
+        assert_eq!(decode(&packet), Ok(vec![sent]));
+    }
+
+    #[test]
+    fn bundles_come_apart_in_order_however_nested() {
+        let a = message("/a", vec![Arg::Int(1)]);
+        let b = message("/b", vec![]);
+        let c = message("/c", vec![Arg::Str(String::from("c"))]);
+        let inner = bundle(&[encode(&b), bundle(&[])]);
+        let packet = bundle(&[encode(&a), inner, encode(&c)]);
+        assert_eq!(decode(&packet), Ok(vec![a, b, c]));
+    }
+
+    #[test]
+    fn arrays_are_flattened() {
+        let mut packet = b"/m\0\0,i[ii]\0\0".to_vec();
+        for v in [1i32, 2, 3] {
+            packet.extend(v.to_be_bytes());
+        }
+        let args = vec![Arg::Int(1), Arg::Int(2), Arg::Int(3)];
+        assert_eq!(decode(&packet), Ok(vec![message("/m", args)]));
+    }
+
+    #[test]
+    fn broken_packets_are_errors() {
+        let packet = encode(&message("/i", vec![Arg::Int(1), Arg::Blob(vec![1; 8])]));
+        for len in [1, 3, 9, 13, packet.len() - 4] {
+            assert!(decode(&packet[..len]).is_err(), "cut at {}", len);
+        }
+        assert_eq!(decode(&packet[..packet.len() - 4]), Err(Error::Truncated));
+        assert_eq!(decode(b"/x\0\0,q\0\0"), Err(Error::UnknownType('q')));
+        assert_eq!(decode(b"/x\0\0i\0\0\0"), Err(Error::BadString));
+        assert_eq!(decode(b"x\0\0\0"), Err(Error::BadAddr(String::from("x"))));
+        assert_eq!(decode(b"/x"), Err(Error::BadString));
+
+        let a = encode(&message("/a", vec![]));
+        let mut packet = bundle(std::slice::from_ref(&a));
+        packet.truncate(packet.len() - 4);
+        assert_eq!(decode(&packet), Err(Error::Truncated));
+        let mut packet = bundle(&[]);
+        packet.extend(6i32.to_be_bytes());
+        packet.extend(&a[..6]);
+        assert_eq!(decode(&packet), Err(Error::BadElement(6)));
+        assert_eq!(decode(BUNDLE_TAG), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn arguments_fill_the_fields_in_order() {
+        let matrix = message(
+            "/matrix",
+            vec![Arg::Int(1), Arg::Int(2), Arg::Int(5), Arg::Int(6)],
+        );
+        assert_eq!(
+            matrix.to_messages().unwrap(),
+            Messages::Matrix(MatrixMessage {
+                matrix: vec![5, 6],
+                rows: Some(1),
+                cols: Some(2),
+            })
+        );
+        let tempo = message("/tempo", vec![Arg::Double(128.5), Arg::Float(3.0)]);
+        assert_eq!(
+            tempo.to_messages().unwrap(),
+            Messages::Tempo(TempoMessage {
+                bpm: 128.5,
+                subdivision: Some(3),
+            })
+        );
+        let wheel = message("/wheel", vec![Arg::Float(64.0)]);
+        assert_eq!(
+            wheel.to_messages().unwrap(),
+            Messages::Wheel(WheelMessage { value: 64 })
+        );
+        assert_eq!(
+            message("/transport/stop", vec![]).to_messages().unwrap(),
+            Messages::TransportStop
+        );
+    }
+
+    #[test]
+    fn unusable_arguments_are_field_errors() {
+        let field = |m: Message| match m.to_messages() {
+            Err(messages::Error::Field { field, .. }) => field,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(field(message("/wheel", vec![Arg::Float(64.5)])), "value");
+        assert_eq!(
+            field(message("/wheel", vec![Arg::Int(1), Arg::Int(2)])),
+            "args"
+        );
+        assert_eq!(field(message("/clock", vec![Arg::Int(1)])), "args");
+        assert_eq!(field(message("/matrix/set", vec![Arg::Int(0)])), "col");
+        assert!(matches!(
+            message("/nope", vec![Arg::Int(1)]).to_messages(),
+            Err(messages::Error::UnknownAddr(addr)) if addr == "/nope"
+        ));
+    }
+
+    #[test]
+    fn matrix_reply_decodes_to_the_same_matrix() {
+        let matrix = Matrix::new(2, 2, vec![1, 0, 0, 1]).unwrap();
+        let reply = decode(&encode(&Message::matrix(&matrix))).unwrap();
+        assert_eq!(
+            reply[0].to_messages().unwrap(),
+            Messages::Matrix(MatrixMessage::from(&matrix))
+        );
+    }
+
+    /// Every address `Messages` has, as serde lists them when it meets an
+    /// unknown one, so a new variant shows up here without anyone adding it.
+    fn every_addr() -> Vec<String> {
+        let error = serde_json::from_value::<Messages>(json!({"addr": "/"})).unwrap_err();
+        let error = error.to_string();
+        let (_, expected) = error.split_once("expected one of ").unwrap();
+        expected
+            .split(", ")
+            .map(|addr| addr.trim_start_matches("or ").trim_matches('`').to_string())
+            .collect()
+    }
+
+    /// One of each, with every optional field set.
+    fn every_message() -> Vec<Messages> {
+        vec![
+            Messages::Matrix(MatrixMessage {
+                matrix: vec![1, 0, 0, 1],
+                rows: Some(2),
+                cols: Some(2),
+            }),
+            Messages::MatrixSet(MatrixSetMessage {
+                row: 1,
+                col: 3,
+                value: 5,
+            }),
+            Messages::MatrixRow(MatrixRowMessage {
+                row: 0,
+                values: vec![1, 2],
+            }),
+            Messages::MatrixClear,
+            Messages::Clock,
+            Messages::Step(StepMessage { position: 4 }),
+            Messages::TransportStart,
+            Messages::TransportStop,
+            Messages::TransportReset,
+            Messages::TransportSeek(SeekMessage { position: 2 }),
+            Messages::Tempo(TempoMessage {
+                bpm: 120.5,
+                subdivision: Some(4),
+            }),
+            Messages::Wheel(WheelMessage { value: 3 }),
+            Messages::Lines(LinesMessage { value: 2 }),
+            Messages::GetMatrix,
+            Messages::Error(ErrorMessage {
+                reason: String::from("no"),
+            }),
+        ]
+    }
+
+    fn json_to_arg(value: &Value) -> Arg {
+        match value {
+            Value::Number(n) if n.is_i64() => Arg::Int(n.as_i64().unwrap() as i32),
+            Value::Number(n) => Arg::Float(n.as_f64().unwrap() as f32),
+            Value::String(s) => Arg::Str(s.clone()),
+            other => panic!("no argument for {}", other),
+        }
+    }
+
+    #[test]
+    fn every_address_has_fields() {
+        let addrs = every_addr();
+        assert!(addrs.len() > 1, "{:?}", addrs);
+        let messages: Vec<_> = every_message().iter().map(Messages::addr).collect();
+        assert_eq!(addrs, messages);
+        for addr in &addrs {
+            assert!(fields(addr).is_some(), "no OSC fields for {}", addr);
+        }
+    }
+
+    #[test]
+    fn fields_cover_every_field_of_the_message() {
+        for expected in every_message() {
+            let value = serde_json::to_value(&expected).unwrap();
+            let (names, rest) = fields(expected.addr()).unwrap();
+            let mut args: Vec<_> = names.iter().map(|name| json_to_arg(&value[name])).collect();
+            if let Some(rest) = rest {
+                args.extend(value[rest].as_array().unwrap().iter().map(json_to_arg));
+            }
+            let listed = names.len() + rest.iter().count() + 1;
+            assert_eq!(value.as_object().unwrap().len(), listed, "{}", value);
+            let osc = message(expected.addr(), args);
+            assert_eq!(osc.to_messages().unwrap(), expected);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::connection::QUEUE_CAPACITY;
+use crate::diagnostics::Diagnostics;
+use crate::listen::SharedMatrix;
+use green_graph::messages::Messages;
+use green_graph::osc;
+
+// the largest UDP payload there is
+const MAX_PACKET: usize = 65536;
+// `/get-matrix` answers, which can be far bigger than the question, sent at
+// once and then per second at most, whoever asks; the sender can be made up
+const REPLY_BURST: f32 = 4.0;
+const REPLIES_PER_SEC: f32 = 10.0;
+
+/// Listens for OSC messages and bundles on `address`, next to the WebSocket
+/// connection or our own server, and hands them on as `Messages`.
+///
+/// The addresses and fields are those of the JSON messages, with the
+/// arguments in the order the fields are listed in `osc`. `/get-matrix` is
+/// answered right away with `/matrix rows cols cells...`, sent back to the
+/// address and port it came from, unless `REPLY_BURST` answers already went out
+/// faster than `REPLIES_PER_SEC`. Everything is counted in `diagnostics`.
+pub fn spawn(
+    address: SocketAddr,
+    matrix: SharedMatrix,
+    diagnostics: Arc<Mutex<Diagnostics>>,
+) -> io::Result<Receiver<Messages>> {
+    let socket = UdpSocket::bind(address)?;
+    println!("listening for OSC on {}", address);
+    Ok(serve(socket, matrix, diagnostics))
+}
+
+/// Does `spawn`'s work on a socket that's already bound.
+fn serve(
+    socket: UdpSocket,
+    matrix: SharedMatrix,
+    diagnostics: Arc<Mutex<Diagnostics>>,
+) -> Receiver<Messages> {
+    let (send, messages) = sync_channel(QUEUE_CAPACITY);
+    std::thread::spawn(move || {
+        let mut buffer = vec![0; MAX_PACKET];
+        let mut replies = ReplyBudget::new();
+        loop {
+            let (len, sender) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) => {
+                    eprintln!("could not receive OSC: {}", e);
+                    continue;
+                }
+            };
+            let packet = match osc::decode(&buffer[..len]) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    let error = format!("{} from {}", e, sender);
+                    diagnostics.lock().unwrap().record_malformed(&error);
+                    continue;
+                }
+            };
+            for message in packet {
+                let result = message.to_messages();
+                diagnostics
+                    .lock()
+                    .unwrap()
+                    .record(&message.to_string(), &result);
+                match result {
+                    Ok(Messages::GetMatrix) if !replies.take() => (),
+                    Ok(Messages::GetMatrix) => {
+                        let reply = osc::Message::matrix(&matrix.lock().unwrap());
+                        if let Err(e) = socket.send_to(&osc::encode(&reply), sender) {
+                            eprintln!("could not answer {}: {}", sender, e);
+                        }
+                    }
+                    Ok(message) => {
+                        if send.send(message).is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => (),
+                }
+            }
+        }
+    });
+    messages
+}
+
+/// How many `/get-matrix` answers we can send right now, refilled over time.
+struct ReplyBudget {
+    left: f32,
+    refilled: Instant,
+}
+
+impl ReplyBudget {
+    fn new() -> Self {
+        Self {
+            left: REPLY_BURST,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Whether there's an answer left, using it up if so.
+    fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.refilled).as_secs_f32() * REPLIES_PER_SEC;
+        self.left = (self.left + refill).min(REPLY_BURST);
+        self.refilled = now;
+        if self.left < 1.0 {
+            return false;
+        }
+        self.left -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use green_graph::matrix::Matrix;
+    use green_graph::messages::{MatrixSetMessage, TempoMessage};
+    use green_graph::osc::{Arg, Message};
+    use std::time::Duration;
+
+    fn message(addr: &str, args: Vec<Arg>) -> Vec<u8> {
+        osc::encode(&Message {
+            addr: String::from(addr),
+            args,
+        })
+    }
+
+    #[test]
+    fn bundles_on_loopback_come_out_as_messages() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let matrix = Matrix::new(1, 2, vec![3, 4]).unwrap();
+        let diagnostics = Arc::new(Mutex::new(Diagnostics::default()));
+        let received = serve(socket, Arc::new(Mutex::new(matrix)), diagnostics.clone());
+
+        let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        for element in [
+            message("/matrix/set", vec![Arg::Int(0), Arg::Int(1), Arg::Int(1)]),
+            message("/nope", vec![Arg::Int(1)]),
+            message("/tempo", vec![Arg::Float(90.0)]),
+            message("/get-matrix", vec![]),
+        ] {
+            bundle.extend((element.len() as i32).to_be_bytes());
+            bundle.extend(element);
+        }
+        let controller = UdpSocket::bind("127.0.0.1:0").unwrap();
+        controller
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        controller.send_to(&bundle, address).unwrap();
+
+        let mut reply = [0; 512];
+        let len = controller.recv(&mut reply).unwrap();
+        let expected = Message {
+            addr: String::from("/matrix"),
+            args: vec![Arg::Int(1), Arg::Int(2), Arg::Int(3), Arg::Int(4)],
+        };
+        assert_eq!(osc::decode(&reply[..len]), Ok(vec![expected]));
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            received.recv_timeout(timeout),
+            Ok(Messages::MatrixSet(MatrixSetMessage {
+                row: 0,
+                col: 1,
+                value: 1
+            }))
+        );
+        assert_eq!(
+            received.recv_timeout(timeout),
+            Ok(Messages::Tempo(TempoMessage {
+                bpm: 90.0,
+                subdivision: None
+            }))
+        );
+        // answered above, not passed on
+        assert!(received.try_recv().is_err());
+
+        controller.send_to(b"junk", address).unwrap();
+        controller
+            .send_to(&message("/clock", vec![]), address)
+            .unwrap();
+        assert_eq!(received.recv_timeout(timeout), Ok(Messages::Clock));
+        let diagnostics = diagnostics.lock().unwrap();
+        assert_eq!(diagnostics.malformed, 1);
+        assert_eq!(diagnostics.unknown, 1);
+    }
+
+    #[test]
+    fn matrix_replies_are_limited() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let matrix = Matrix::new(1, 2, vec![3, 4]).unwrap();
+        let diagnostics = Arc::new(Mutex::new(Diagnostics::default()));
+        let _received = serve(socket, Arc::new(Mutex::new(matrix)), diagnostics);
+
+        let controller = UdpSocket::bind("127.0.0.1:0").unwrap();
+        controller
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        let get_matrix = message("/get-matrix", vec![]);
+        for _ in 0..10 {
+            bundle.extend((get_matrix.len() as i32).to_be_bytes());
+            bundle.extend(&get_matrix);
+        }
+        controller.send_to(&bundle, address).unwrap();
+        let mut reply = [0; 512];
+        let mut replies = 0;
+        while controller.recv(&mut reply).is_ok() {
+            replies += 1;
+        }
+        assert_eq!(replies, REPLY_BURST as usize);
+    }
+}
@@ -0,0 +1,194 @@
+use serde::Deserialize;
+
+/// Which stretch of the timeline a panel shows, before any extra offset.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// The window's width of steps right before `Now`'s.
+    Past,
+    /// Mostly the recent past, with the next few steps (the future position of
+    /// the width) on the right.
+    Now,
+    /// The window's width of steps right after `Now`'s, straight from the matrix.
+    Future,
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Past => "past",
+            Role::Now => "now",
+            Role::Future => "future",
+        }
+    }
+}
+
+/// A window onto the shared timeline, `steps_on_screen` steps wide.
+///
+/// `role` places it next to "now"; `offset` moves it by that many more steps,
+/// negative into the past.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Panel {
+    pub role: Role,
+    pub offset: isize,
+}
+
+impl Panel {
+    pub fn new(role: Role) -> Self {
+        Self { role, offset: 0 }
+    }
+
+    /// The step shown in the panel's leftmost slot, relative to the current
+    /// step (0); `future_steps` of the `steps_on_screen` are still to come in
+    /// the `Now` panel.
+    pub fn first_step(&self, steps_on_screen: usize, future_steps: usize) -> isize {
+        let width = steps_on_screen as isize;
+        let now = future_steps as isize - width;
+        let first = match self.role {
+            Role::Past => now - width,
+            Role::Now => now,
+            Role::Future => now + width,
+        };
+        first + self.offset
+    }
+
+    pub fn window(&self, steps_on_screen: usize, future_steps: usize) -> TimeWindow {
+        TimeWindow {
+            first: self.first_step(steps_on_screen, future_steps) as f32,
+            steps: steps_on_screen as f32,
+        }
+    }
+}
+
+/// The stretch of time a window shows: `steps` steps starting at `first`,
+/// relative to the current step. Either can be fractional when spanning.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeWindow {
+    pub first: f32,
+    pub steps: f32,
+}
+
+/// One window's share of the spanning canvas.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slice {
+    pub window: usize,
+    // relative to the other slices' widths
+    pub width: f32,
+}
+
+/// Spanning mode: the windows sit side by side as one wide canvas, and the
+/// timeline runs across all of them as if it were a single `Now` panel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    // left to right
+    slices: Vec<Slice>,
+}
+
+impl Span {
+    /// Every window gets the same width, in window order.
+    pub fn even(windows: usize) -> Self {
+        Self {
+            slices: (0..windows)
+                .map(|window| Slice { window, width: 1.0 })
+                .collect(),
+        }
+    }
+
+    /// Parses the windows from left to right, each with an optional relative
+    /// width, e.g. `1:2,0:1`. Every one of the `windows` has to be there once.
+    pub fn from_spec(spec: &str, windows: usize) -> Option<Self> {
+        let mut slices = Vec::new();
+        for part in spec.split(',') {
+            let mut fields = part.splitn(2, ':').map(str::trim);
+            let window = fields.next()?.parse().ok()?;
+            let width: f32 = match fields.next() {
+                Some(width) => width.parse().ok()?,
+                None => 1.0,
+            };
+            if window >= windows
+                || !width.is_finite()
+                || width <= 0.0
+                || slices.iter().any(|s: &Slice| s.window == window)
+            {
+                return None;
+            }
+            slices.push(Slice { window, width });
+        }
+        if slices.len() != windows {
+            return None;
+        }
+        Some(Self { slices })
+    }
+
+    pub fn slices(&self) -> &[Slice] {
+        &self.slices
+    }
+
+    /// The part of `canvas` that `window` shows.
+    pub fn window(&self, window: usize, canvas: TimeWindow) -> Option<TimeWindow> {
+        let total: f32 = self.slices.iter().map(|s| s.width).sum();
+        let mut first = canvas.first;
+        for slice in &self.slices {
+            let steps = canvas.steps * slice.width / total;
+            if slice.window == window {
+                return Some(TimeWindow { first, steps });
+            }
+            first += steps;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CANVAS: TimeWindow = TimeWindow {
+        first: -12.0,
+        steps: 16.0,
+    };
+
+    #[test]
+    fn spec_lists_windows_left_to_right() {
+        let span = Span::from_spec("2, 0:3,1", 3).unwrap();
+        let order: Vec<_> = span.slices().iter().map(|s| s.window).collect();
+        assert_eq!(order, vec![2, 0, 1]);
+        assert_eq!(Span::from_spec("0,1,2", 3), Some(Span::even(3)));
+    }
+
+    #[test]
+    fn windows_share_the_canvas_by_width() {
+        let span = Span::from_spec("1:3,0:1", 2).unwrap();
+        assert_eq!(
+            span.window(1, CANVAS),
+            Some(TimeWindow {
+                first: -12.0,
+                steps: 12.0
+            })
+        );
+        assert_eq!(
+            span.window(0, CANVAS),
+            Some(TimeWindow {
+                first: 0.0,
+                steps: 4.0
+            })
+        );
+        assert_eq!(span.window(2, CANVAS), None);
+    }
+
+    #[test]
+    fn spec_needs_every_window_once() {
+        // duplicate
+        assert_eq!(Span::from_spec("0,0", 2), None);
+        // missing
+        assert_eq!(Span::from_spec("0", 2), None);
+        // out of range
+        assert_eq!(Span::from_spec("0,2", 2), None);
+        // bad widths
+        assert_eq!(Span::from_spec("0:0,1", 2), None);
+        assert_eq!(Span::from_spec("0:-1,1", 2), None);
+        assert_eq!(Span::from_spec("0:inf,1", 2), None);
+        assert_eq!(Span::from_spec("0:x,1", 2), None);
+        assert_eq!(Span::from_spec("", 1), None);
+    }
+}
@@ -0,0 +1,41 @@
+/// When an incoming `/matrix` replaces the one that's playing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quantize {
+    /// Right away, in the middle of a step if need be.
+    Immediate,
+    /// At the next step boundary.
+    Step,
+    /// When the matrix position wraps around to the start of the cycle.
+    Cycle,
+}
+
+impl Quantize {
+    pub const ALL: [Quantize; 3] = [Quantize::Immediate, Quantize::Step, Quantize::Cycle];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Quantize::Immediate => "immediate",
+            Quantize::Step => "step",
+            Quantize::Cycle => "cycle",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|q| q.name() == name)
+    }
+
+    /// The mode after this one, wrapping around.
+    pub fn next(&self) -> Self {
+        let i = Self::ALL.iter().position(|q| q == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// Whether a pending matrix should be swapped in at a step boundary.
+    /// `position` is the matrix position after the step.
+    pub fn is_due(&self, position: usize) -> bool {
+        match self {
+            Quantize::Immediate | Quantize::Step => true,
+            Quantize::Cycle => position == 0,
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+use std::iter::FromIterator;
+
+/// A fixed-length run of step values, oldest first.
+///
+/// Pushing a new value drops the oldest one, and the length only changes when
+/// asked to. Every operation that runs once per step is O(1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ring<T> {
+    slots: VecDeque<T>,
+}
+
+impl<T: Clone> Ring<T> {
+    pub fn new(len: usize, fill: T) -> Self {
+        Self {
+            slots: VecDeque::from(vec![fill; len]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Appends `value` as the newest slot and returns the oldest, which falls out.
+    pub fn push(&mut self, value: T) -> Option<T> {
+        self.slots.push_back(value);
+        self.slots.pop_front()
+    }
+
+    /// The value `back` steps before the newest one, as long as it's still kept.
+    pub fn back(&self, back: usize) -> Option<&T> {
+        let i = self.slots.len().checked_sub(back + 1)?;
+        self.slots.get(i)
+    }
+
+    /// Adds or drops slots at the old end until there are `len`.
+    pub fn resize(&mut self, len: usize, fill: T) {
+        while self.slots.len() < len {
+            self.slots.push_front(fill.clone());
+        }
+        while self.slots.len() > len {
+            self.slots.pop_front();
+        }
+    }
+
+    pub fn fill(&mut self, value: T) {
+        self.slots.iter_mut().for_each(|slot| *slot = value.clone());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter()
+    }
+}
+
+impl<T> FromIterator<T> for Ring<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self {
+            slots: iter.into_iter().collect(),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+// How quickly the measured tick interval follows changes (0..1, higher is faster).
+const SMOOTHING: f32 = 0.2;
+// The scroll never quite reaches the next step on its own; it waits for the tick.
+const MAX_PHASE: f32 = 0.999;
+// Without a tick for this many intervals (and at least `MIN_TIMEOUT` seconds),
+// we go back to our own tempo.
+const TIMEOUT_INTERVALS: f32 = 4.0;
+const MIN_TIMEOUT: f32 = 1.0;
+
+/// Whether we started or stopped following the server's clock.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncChange {
+    Following,
+    /// No tick for too long; back to our own tempo.
+    Lost,
+}
+
+/// Follows the server's clock: every `/clock` tick or `/step` message is one step.
+///
+/// Step boundaries happen exactly at the ticks; in between, the phase within the
+/// step is interpolated from the measured tick interval, so the scroll is smooth
+/// but can never run ahead of the server.
+#[derive(Debug, Default)]
+pub struct ClockSync {
+    // ticks since the last `advance`; `Some` carries an absolute position
+    ticks: Vec<Option<usize>>,
+    // seconds since the last tick
+    since_tick: f32,
+    // smoothed seconds between ticks, once we've seen two
+    interval: Option<f32>,
+    synced: bool,
+}
+
+impl ClockSync {
+    /// Queues a tick; `position` is the matrix position for `/step` messages.
+    pub fn tick(&mut self, position: Option<usize>) {
+        self.ticks.push(position);
+    }
+
+    /// Moves on by `dt` seconds and returns the ticks queued since the last call,
+    /// in order, each of them one step to take, and whether that started or
+    /// stopped the following.
+    pub fn advance(&mut self, dt: f32) -> (Vec<Option<usize>>, Option<SyncChange>) {
+        self.since_tick += dt;
+        let mut change = None;
+        let ticks = std::mem::take(&mut self.ticks);
+        if !ticks.is_empty() {
+            if self.synced {
+                // several ticks within one frame share the time since the last one
+                let measured = self.since_tick / ticks.len() as f32;
+                self.interval = Some(match self.interval {
+                    Some(interval) => interval + SMOOTHING * (measured - interval),
+                    None => measured,
+                });
+            } else {
+                change = Some(SyncChange::Following);
+                self.synced = true;
+            }
+            self.since_tick = 0.0;
+        } else if self.synced && self.since_tick > self.timeout() {
+            change = Some(SyncChange::Lost);
+            self.synced = false;
+            self.interval = None;
+        }
+        (ticks, change)
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// How far we are into the current step, from 0 up to (not including) 1.
+    pub fn phase(&self) -> f32 {
+        match self.interval {
+            Some(interval) if interval > 0.0 => (self.since_tick / interval).min(MAX_PHASE),
+            _ => 0.0,
+        }
+    }
+
+    fn timeout(&self) -> f32 {
+        self.interval
+            .map(|interval| interval * TIMEOUT_INTERVALS)
+            .unwrap_or(0.0)
+            .max(MIN_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_starting_and_stopping_to_follow() {
+        let mut sync = ClockSync::default();
+        assert_eq!(sync.advance(0.1), (vec![], None));
+        sync.tick(Some(3));
+        assert_eq!(
+            sync.advance(0.1),
+            (vec![Some(3)], Some(SyncChange::Following))
+        );
+        sync.tick(None);
+        assert_eq!(sync.advance(0.25), (vec![None], None));
+        assert!(sync.is_synced());
+        assert_eq!(
+            sync.advance(MIN_TIMEOUT + 0.1),
+            (vec![], Some(SyncChange::Lost))
+        );
+        assert!(!sync.is_synced());
+    }
+}
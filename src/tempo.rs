@@ -0,0 +1,226 @@
+pub const DEFAULT_BPM: f32 = 120.0;
+/// Steps per beat: sixteenth notes.
+pub const DEFAULT_SUBDIVISION: u32 = 4;
+pub const DEFAULT_RAMP_SECS: f32 = 0.5;
+/// Far beyond anything playable, but keeps the step count per frame sane.
+pub const MAX_BPM: f32 = 1000.0;
+pub const MAX_SUBDIVISION: u32 = 64;
+
+/// Whether `bpm` is a tempo we can run at: finite, from 0 to `MAX_BPM`.
+pub fn is_valid_bpm(bpm: f32) -> bool {
+    (0.0..=MAX_BPM).contains(&bpm)
+}
+
+/// Tempo in beats per minute, gliding to a new value instead of jumping.
+#[derive(Debug, Clone)]
+pub struct Tempo {
+    from: f32,
+    target: f32,
+    // seconds into the current ramp
+    elapsed: f32,
+    ramp_secs: f32,
+    subdivision: u32,
+}
+
+impl Tempo {
+    pub fn new(bpm: f32, subdivision: u32, ramp_secs: f32) -> Self {
+        Self {
+            from: bpm,
+            target: bpm,
+            elapsed: ramp_secs,
+            ramp_secs,
+            subdivision,
+        }
+    }
+
+    /// The current, possibly still ramping, tempo.
+    pub fn bpm(&self) -> f32 {
+        if self.ramp_secs <= 0.0 || self.elapsed >= self.ramp_secs {
+            return self.target;
+        }
+        self.from + (self.target - self.from) * (self.elapsed / self.ramp_secs)
+    }
+
+    /// Starts ramping from the current tempo to `bpm`.
+    pub fn set_bpm(&mut self, bpm: f32) {
+        self.from = self.bpm();
+        self.target = bpm;
+        self.elapsed = 0.0;
+    }
+
+    /// Takes `ramp_secs` for tempo changes from now on; a ramp under way
+    /// ends no later than that.
+    pub fn set_ramp_secs(&mut self, ramp_secs: f32) {
+        self.ramp_secs = ramp_secs;
+        self.elapsed = self.elapsed.min(ramp_secs);
+    }
+
+    pub fn subdivision(&self) -> u32 {
+        self.subdivision
+    }
+
+    pub fn set_subdivision(&mut self, subdivision: u32) {
+        self.subdivision = subdivision;
+    }
+
+    pub fn advance(&mut self, dt: f32) {
+        self.elapsed = (self.elapsed + dt).min(self.ramp_secs);
+    }
+
+    pub fn steps_per_second(&self) -> f32 {
+        self.bpm() / 60.0 * self.subdivision as f32
+    }
+
+    /// How many steps the next `dt` seconds take, following the ramp through
+    /// them rather than using the tempo at either end.
+    pub fn steps_in(&self, dt: f32) -> f64 {
+        let (dt, target) = (dt as f64, self.target as f64);
+        let ramping = if self.ramp_secs > 0.0 {
+            ((self.ramp_secs - self.elapsed) as f64).clamp(0.0, dt)
+        } else {
+            0.0
+        };
+        let start = self.bpm() as f64;
+        let end = if ramping > 0.0 {
+            let t = (self.elapsed as f64 + ramping) / self.ramp_secs as f64;
+            self.from as f64 + (target - self.from as f64) * t
+        } else {
+            target
+        };
+        // the ramp is linear, so its average is halfway between the ends
+        let beats = ((start + end) / 2.0 * ramping + target * (dt - ramping)) / 60.0;
+        beats * self.subdivision as f64
+    }
+}
+
+/// Maps the hardware encoder's `/wheel` value (0-255) to a tempo.
+///
+/// `exponent` bends the curve: above 1 gives finer control at the slow end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WheelCurve {
+    pub min_bpm: f32,
+    pub max_bpm: f32,
+    pub exponent: f32,
+}
+
+impl Default for WheelCurve {
+    /// The range the wheel covered before it was mapped to BPM.
+    fn default() -> Self {
+        Self {
+            min_bpm: 0.0,
+            max_bpm: 160.0,
+            exponent: 1.0,
+        }
+    }
+}
+
+impl WheelCurve {
+    /// Parses `min,max,exponent`, e.g. `40,180,2`; both tempos have to be
+    /// valid BPM and the exponent above 0.
+    pub fn from_spec(spec: &str) -> Option<Self> {
+        let mut parts = spec.split(',').map(|p| p.trim().parse::<f32>().ok());
+        let curve = Self {
+            min_bpm: parts.next()??,
+            max_bpm: parts.next()??,
+            exponent: parts.next()??,
+        };
+        let valid = is_valid_bpm(curve.min_bpm)
+            && is_valid_bpm(curve.max_bpm)
+            && curve.exponent.is_finite()
+            && curve.exponent > 0.0;
+        if parts.next().is_some() || !valid {
+            return None;
+        }
+        Some(curve)
+    }
+
+    pub fn bpm(&self, value: u8) -> f32 {
+        let x = value as f32 / u8::MAX as f32;
+        self.min_bpm + (self.max_bpm - self.min_bpm) * x.powf(self.exponent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn steady_tempo_takes_bpm_times_subdivision_steps() {
+        let tempo = Tempo::new(120.0, 4, DEFAULT_RAMP_SECS);
+        assert!(close(tempo.steps_in(1.0), 8.0));
+        assert!(close(tempo.steps_in(0.25), 2.0));
+    }
+
+    #[test]
+    fn ramp_is_linear_and_ends_on_target() {
+        let mut tempo = Tempo::new(60.0, 4, 0.5);
+        tempo.set_bpm(120.0);
+        assert_eq!(tempo.bpm(), 60.0);
+        tempo.advance(0.25);
+        assert!(close(tempo.bpm() as f64, 90.0));
+        tempo.advance(1.0);
+        assert_eq!(tempo.bpm(), 120.0);
+    }
+
+    #[test]
+    fn ramp_steps_do_not_depend_on_frame_length() {
+        let mut ramp = Tempo::new(60.0, 4, 0.5);
+        ramp.set_bpm(120.0);
+
+        let mut long = ramp.clone();
+        let one_frame = long.steps_in(1.0);
+        long.advance(1.0);
+
+        let mut short = ramp;
+        let mut many_frames = 0.0;
+        for _ in 0..1000 {
+            many_frames += short.steps_in(0.001);
+            short.advance(0.001);
+        }
+        // half a second at 90 on average, then half a second at 120
+        assert!(close(one_frame, 7.0));
+        assert!(close(many_frames, 7.0));
+    }
+
+    #[test]
+    fn ramp_time_change_cuts_a_running_ramp_short() {
+        let mut tempo = Tempo::new(60.0, 4, 2.0);
+        tempo.set_bpm(120.0);
+        tempo.advance(0.5);
+        tempo.set_ramp_secs(0.25);
+        assert_eq!(tempo.bpm(), 120.0);
+    }
+
+    #[test]
+    fn bpm_has_to_be_finite_and_in_range() {
+        assert!(is_valid_bpm(0.0));
+        assert!(is_valid_bpm(MAX_BPM));
+        assert!(!is_valid_bpm(-1.0));
+        assert!(!is_valid_bpm(1e30));
+        assert!(!is_valid_bpm(f32::NAN));
+        assert!(!is_valid_bpm(f32::INFINITY));
+    }
+
+    #[test]
+    fn wheel_curve_spec() {
+        let curve = WheelCurve::from_spec("40, 180, 2").unwrap();
+        assert_eq!(curve.bpm(0), 40.0);
+        assert_eq!(curve.bpm(u8::MAX), 180.0);
+        assert!(curve.bpm(128) < 110.0);
+        for bad in [
+            "40,180",
+            "40,180,2,1",
+            "nan,180,1",
+            "-10,180,1",
+            "40,1e30,1",
+            "40,180,0",
+            "40,180,nan",
+        ] {
+            assert_eq!(WheelCurve::from_spec(bad), None, "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,694 @@
+use std::cmp::{max, min};
+use std::fmt;
+
+use crate::clock::StepClock;
+use crate::generator::{Algorithm, Generator};
+use crate::matrix::{Matrix, ShapeError};
+use crate::messages::Messages;
+use crate::panel::{Panel, Span, TimeWindow};
+use crate::quantize::Quantize;
+use crate::ring::Ring;
+use crate::sync::{ClockSync, SyncChange};
+use crate::tempo::{self, Tempo, WheelCurve};
+use crate::transport::Transport;
+
+/// Where "now" sits in the `Now` panel, as a fraction of its width from the
+/// right, unless `set_future_position` says otherwise.
+pub const DEFAULT_FUTURE_POSITION: f32 = 0.2;
+// shape of the patterns the demo generator makes
+const DEMO_ROWS: usize = 4;
+const DEMO_STEPS: usize = 16;
+// how long a patched cell stays highlighted
+const HIGHLIGHT_SECS: f32 = 1.0;
+pub const MIN_STEPS_ON_SCREEN: usize = 16;
+// zooming out stops here, or at the width we started with if that's wider
+const MAX_STEPS_ON_SCREEN: usize = 64;
+pub const DEFAULT_LINES: usize = 4;
+
+/// What one `Timeline::advance` did.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Advance {
+    /// Steps taken, including any only counted after a long frame.
+    pub steps: usize,
+    /// Set when we started or stopped following the server's clock.
+    pub sync: Option<SyncChange>,
+}
+
+/// A message the timeline couldn't apply, and why.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejected {
+    pub addr: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.addr, self.reason)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Everything that moves: the matrix, the clocks and the history of played
+/// steps the panels show.
+///
+/// Nothing in here knows about windows or pixels; feed it messages with
+/// `apply` and time with `advance`.
+pub struct Timeline {
+    matrix: Matrix,
+    // the next matrix, waiting for the boundary `quantize` asks for
+    pending: Option<Matrix>,
+    quantize: Quantize,
+    // per cell, 1.0 right after a patch changed it, fading to 0.0
+    highlights: Vec<f32>,
+    // per matrix row, the values played so far, newest last; as long as the
+    // panel furthest in the past needs
+    history: Vec<Ring<i32>>,
+    panels: Vec<Panel>,
+    // when set, the panels' windows share one canvas instead
+    span: Option<Span>,
+    position: usize,
+    num_steps_on_screen: usize,
+    max_steps_on_screen: usize,
+    future_position: f32,
+    // musical time; views derive their scroll offset from its phase
+    clock: StepClock,
+    // drives the steps while the server sends `/clock` or `/step`
+    sync: ClockSync,
+    transport: Transport,
+    tempo: Tempo,
+    // how `/wheel` values map to BPM
+    wheel: WheelCurve,
+    // how many lanes `/lines` asked for
+    lines: usize,
+    // generates a new pattern at cycle starts while `generating` is on
+    generator: Generator,
+    generating: bool,
+}
+
+impl Timeline {
+    pub fn new(
+        num_steps_on_screen: usize,
+        quantize: Quantize,
+        tempo: Tempo,
+        wheel: WheelCurve,
+        panels: Vec<Panel>,
+        span: Option<Span>,
+    ) -> Self {
+        let matrix = Matrix::zeros(DEMO_ROWS, DEMO_STEPS);
+        let mut timeline = Self {
+            highlights: vec![0.0; matrix.cells().len()],
+            history: vec![Ring::new(0, 0); matrix.rows()],
+            matrix,
+            pending: None,
+            quantize,
+            panels,
+            span,
+            position: 0,
+            num_steps_on_screen,
+            max_steps_on_screen: max(num_steps_on_screen, MAX_STEPS_ON_SCREEN),
+            future_position: DEFAULT_FUTURE_POSITION,
+            clock: StepClock::default(),
+            sync: ClockSync::default(),
+            transport: Transport::Playing,
+            tempo,
+            wheel,
+            lines: DEFAULT_LINES,
+            generator: Generator::default(),
+            generating: false,
+        };
+        timeline.resize_history();
+        timeline
+    }
+
+    pub fn matrix(&self) -> &Matrix {
+        &self.matrix
+    }
+
+    pub fn pending(&self) -> Option<&Matrix> {
+        self.pending.as_ref()
+    }
+
+    /// The matrix column of the current step.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn num_steps_on_screen(&self) -> usize {
+        self.num_steps_on_screen
+    }
+
+    /// How many of the steps on screen are still to come in the `Now` panel.
+    pub fn future_steps(&self) -> usize {
+        future_steps(self.num_steps_on_screen, self.future_position)
+    }
+
+    pub fn future_position(&self) -> f32 {
+        self.future_position
+    }
+
+    /// Moves "now" to `position` of the `Now` panel's width from the right,
+    /// keeping as much history as the panels can show from there.
+    pub fn set_future_position(&mut self, position: f32) {
+        self.future_position = position.clamp(0.0, 1.0);
+        self.resize_history();
+    }
+
+    pub fn panels(&self) -> &[Panel] {
+        &self.panels
+    }
+
+    /// Swaps in new panel definitions, one per window as before, keeping the
+    /// current step and as much history as they can show.
+    pub fn set_panels(&mut self, panels: Vec<Panel>) {
+        self.panels = panels;
+        self.resize_history();
+    }
+
+    /// Joins the windows into one canvas, or with `None` back into panels.
+    pub fn set_span(&mut self, span: Option<Span>) {
+        self.span = span;
+        self.resize_history();
+    }
+
+    pub fn is_spanning(&self) -> bool {
+        self.span.is_some()
+    }
+
+    /// The stretch of time the window of panel `i` shows.
+    pub fn window(&self, i: usize) -> Option<TimeWindow> {
+        match self.span.as_ref() {
+            Some(span) => span.window(i, self.canvas()),
+            None => {
+                let panel = self.panels.get(i)?;
+                Some(panel.window(self.num_steps_on_screen, self.future_steps()))
+            }
+        }
+    }
+
+    /// When spanning, the whole canvas: one window's width of steps for every
+    /// panel, with "now" as far from the right as in a `Now` panel.
+    fn canvas(&self) -> TimeWindow {
+        canvas(
+            self.num_steps_on_screen,
+            self.panels.len(),
+            self.future_position,
+        )
+    }
+
+    /// The value of `row` at `step`, relative to the current step: what was
+    /// played for the past (0 where nothing was), the matrix for the future.
+    pub fn value(&self, row: usize, step: isize) -> i32 {
+        if step > 0 {
+            if row >= self.matrix.rows() {
+                return 0;
+            }
+            return self
+                .matrix
+                .get(row, self.step_col(step, self.matrix.cols()));
+        }
+        self.history
+            .get(row)
+            .and_then(|h| h.back(step.unsigned_abs()))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn clock(&self) -> &StepClock {
+        &self.clock
+    }
+
+    pub fn tempo(&self) -> &Tempo {
+        &self.tempo
+    }
+
+    pub fn set_tempo_ramp(&mut self, ramp_secs: f32) {
+        self.tempo.set_ramp_secs(ramp_secs);
+    }
+
+    pub fn set_wheel(&mut self, wheel: WheelCurve) {
+        self.wheel = wheel;
+    }
+
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    pub fn set_transport(&mut self, transport: Transport) {
+        self.transport = transport;
+    }
+
+    pub fn quantize(&self) -> Quantize {
+        self.quantize
+    }
+
+    pub fn set_quantize(&mut self, quantize: Quantize) {
+        self.quantize = quantize;
+    }
+
+    pub fn set_lines(&mut self, lines: usize) {
+        self.lines = lines;
+    }
+
+    /// Number of lanes to draw: what `/lines` asked for, as far as the matrix has rows.
+    pub fn lanes(&self) -> usize {
+        min(self.lines, self.matrix.rows())
+    }
+
+    /// Whether the demo generator replaces the matrix at cycle starts.
+    pub fn set_generating(&mut self, generating: bool) {
+        self.generating = generating;
+    }
+
+    /// Switches the demo generator to its next algorithm.
+    pub fn next_algorithm(&mut self) -> Algorithm {
+        self.generator.next_algorithm()
+    }
+
+    /// Zooms to `steps` on screen, and lets zooming out go that far from now on.
+    pub fn set_num_steps_on_screen(&mut self, steps: usize) {
+        self.num_steps_on_screen = max(steps, MIN_STEPS_ON_SCREEN);
+        self.max_steps_on_screen = max(self.num_steps_on_screen, MAX_STEPS_ON_SCREEN);
+        self.resize_history();
+    }
+
+    pub fn increment_num_steps_on_screen(&mut self) {
+        self.num_steps_on_screen = min(self.num_steps_on_screen + 1, self.max_steps_on_screen);
+    }
+
+    pub fn decrement_num_steps_on_screen(&mut self) {
+        self.num_steps_on_screen = max(self.num_steps_on_screen - 1, MIN_STEPS_ON_SCREEN);
+    }
+
+    /// Moves time on by `dt` seconds.
+    ///
+    /// Follows the server's clock while it's ticking and runs on our own tempo
+    /// otherwise. A long `dt` takes every step it covers, one after the other,
+    /// as far as that still shows; steps further back are only counted.
+    pub fn advance(&mut self, dt: f32) -> Advance {
+        for h in self.highlights.iter_mut() {
+            *h = (*h - dt / HIGHLIGHT_SECS).max(0.0);
+        }
+        let (ticks, sync) = self.sync.advance(dt);
+        let playing = self.transport.is_playing();
+        let taken = if !playing {
+            0
+        } else if self.sync.is_synced() {
+            self.clock
+                .sync_to(ticks.len(), self.sync.phase(), &self.tempo);
+            let taken = ticks.len();
+            let replayed = self.replayed(taken);
+            for (i, position) in ticks.into_iter().enumerate() {
+                let next = position.unwrap_or(self.position + 1);
+                if i < taken - replayed {
+                    self.position = next % self.matrix.cols();
+                } else {
+                    self.advance_step(next);
+                }
+            }
+            taken
+        } else {
+            // every boundary since the last call, so a hitch doesn't lose steps
+            let taken = self.clock.advance(dt, &self.tempo);
+            let replayed = self.replayed(taken);
+            let skipped = (taken - replayed) % self.matrix.cols();
+            self.position = (self.position + skipped) % self.matrix.cols();
+            for _ in 0..replayed {
+                self.advance_step(self.position + 1);
+            }
+            taken
+        };
+        self.tempo.advance(dt);
+        Advance { steps: taken, sync }
+    }
+
+    /// How many of `taken` steps to go through one by one: enough to refill
+    /// the history and reach a cycle start for anything pending.
+    fn replayed(&self, taken: usize) -> usize {
+        min(taken, self.history_len() + self.matrix.cols())
+    }
+
+    /// Moves the timeline on by one step, to matrix position `next`, and
+    /// records what's played there.
+    fn advance_step(&mut self, next: usize) {
+        self.position = next % self.matrix.cols();
+        if self.quantize.is_due(self.position) {
+            if let Some(matrix) = self.pending.take() {
+                self.set_matrix(matrix);
+            }
+        }
+        if self.position == 0 && self.generating {
+            let generated = self.generator.on_cycle(DEMO_ROWS, DEMO_STEPS, &self.matrix);
+            if let Some(matrix) = generated {
+                self.set_matrix(matrix);
+            }
+        }
+        for (row, h) in self.history.iter_mut().enumerate() {
+            h.push(self.matrix.get(row, self.position));
+        }
+    }
+
+    /// Back to the start of the cycle with an empty history.
+    pub fn reset(&mut self) {
+        self.position = 0;
+        self.clock.reset();
+        for h in self.history.iter_mut() {
+            h.fill(0);
+        }
+    }
+
+    /// Jumps to `position` and fills the history as if the current matrix had
+    /// been playing all along up to there.
+    pub fn seek(&mut self, position: usize) {
+        self.position = position % self.matrix.cols();
+        self.clock.rewind_step();
+        for row in 0..self.matrix.rows() {
+            let len = self.history[row].len();
+            self.history[row] = (0..len)
+                .rev()
+                .map(|back| {
+                    let col = self.matrix.step_before(self.position, back);
+                    self.matrix.get(row, col)
+                })
+                .collect();
+        }
+    }
+
+    /// Seeks one step back, wrapping to the end of the cycle.
+    pub fn seek_back(&mut self) {
+        self.seek(self.matrix.step_before(self.position, 1));
+    }
+
+    pub fn apply(&mut self, message: Messages) -> Result<(), Rejected> {
+        match message {
+            Messages::Matrix(m) => {
+                let (rows, cols) = m.shape();
+                match Matrix::new(rows, cols, m.matrix) {
+                    Ok(matrix) if self.quantize == Quantize::Immediate => self.set_matrix(matrix),
+                    Ok(matrix) => self.pending = Some(matrix),
+                    Err(e) => return Err(rejected("/matrix", e)),
+                }
+            }
+            Messages::MatrixSet(m) => {
+                self.patch("/matrix/set", |matrix| matrix.set(m.row, m.col, m.value))?;
+            }
+            Messages::MatrixRow(m) => {
+                self.patch("/matrix/row", |matrix| matrix.set_row(m.row, &m.values))?;
+            }
+            Messages::MatrixClear => {
+                self.patch("/matrix/clear", |matrix| {
+                    matrix.clear();
+                    Ok(())
+                })?;
+            }
+            Messages::Clock => self.sync.tick(None),
+            Messages::Step(m) => self.sync.tick(Some(m.position)),
+            Messages::TransportStart => self.transport = Transport::Playing,
+            Messages::TransportStop => self.transport = Transport::Stopped,
+            Messages::TransportReset => self.reset(),
+            Messages::TransportSeek(m) => self.seek(m.position),
+            Messages::Tempo(m) => {
+                if !tempo::is_valid_bpm(m.bpm) {
+                    return Err(rejected(
+                        "/tempo",
+                        format!("invalid bpm {}, must be 0 to {}", m.bpm, tempo::MAX_BPM),
+                    ));
+                } else if let Some(subdivision) = m.subdivision {
+                    if !(1..=tempo::MAX_SUBDIVISION).contains(&subdivision) {
+                        return Err(rejected(
+                            "/tempo",
+                            format!("subdivision must be 1 to {}", tempo::MAX_SUBDIVISION),
+                        ));
+                    }
+                }
+                self.tempo.set_bpm(m.bpm);
+                if let Some(subdivision) = m.subdivision {
+                    self.tempo.set_subdivision(subdivision);
+                }
+            }
+            Messages::Wheel(m) => {
+                self.tempo.set_bpm(self.wheel.bpm(m.value));
+            }
+            Messages::Lines(m) => {
+                self.lines = m.value;
+            }
+            // only ever sent to the server
+            Messages::GetMatrix | Messages::Error(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Swaps in a new matrix, adapting the history and position if its shape changed.
+    pub fn set_matrix(&mut self, matrix: Matrix) {
+        let len = self.history_len();
+        self.history.resize(matrix.rows(), Ring::new(len, 0));
+        self.position %= matrix.cols();
+        // anything still pending is older than this one
+        self.pending = None;
+        self.highlights = vec![0.0; matrix.cells().len()];
+        self.matrix = matrix;
+    }
+
+    /// Edits the matrix in place and highlights the cells that changed.
+    ///
+    /// While a matrix is pending, the edit goes there instead so it isn't lost
+    /// when the pending matrix comes in.
+    fn patch<F>(&mut self, addr: &'static str, edit: F) -> Result<(), Rejected>
+    where
+        F: FnOnce(&mut Matrix) -> Result<(), ShapeError>,
+    {
+        if let Some(pending) = self.pending.as_mut() {
+            return edit(pending).map_err(|e| rejected(addr, e));
+        }
+        let old = self.matrix.clone();
+        edit(&mut self.matrix).map_err(|e| rejected(addr, e))?;
+        let changed = old.cells().iter().zip(self.matrix.cells().iter());
+        for (h, (a, b)) in self.highlights.iter_mut().zip(changed) {
+            if a != b {
+                *h = 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// The column of a matrix with `cols` steps at `step`, relative to the
+    /// current step.
+    pub fn step_col(&self, step: isize, cols: usize) -> usize {
+        (self.position as isize + step).rem_euclid(cols as isize) as usize
+    }
+
+    /// How strongly `row` at `step` is highlighted, from 0 to 1. Only future
+    /// steps show the current matrix, so only they light up while their cell
+    /// was recently patched.
+    pub fn highlight(&self, row: usize, step: isize) -> f32 {
+        if step <= 0 {
+            return 0.0;
+        }
+        let cols = self.matrix.cols();
+        self.highlights[row * cols + self.step_col(step, cols)]
+    }
+
+    /// Replaces the matrix with a freshly generated demo pattern.
+    pub fn regenerate(&mut self) {
+        let matrix = self.generator.generate(DEMO_ROWS, DEMO_STEPS, &self.matrix);
+        self.set_matrix(matrix);
+    }
+
+    /// Steps of history the windows can show at the widest zoom, including
+    /// the current one.
+    fn history_len(&self) -> usize {
+        let steps = self.max_steps_on_screen;
+        let furthest = if self.span.is_some() {
+            canvas(steps, self.panels.len(), self.future_position)
+                .first
+                .floor() as isize
+        } else {
+            let future = future_steps(steps, self.future_position);
+            self.panels
+                .iter()
+                .map(|p| p.first_step(steps, future))
+                .min()
+                .unwrap_or(0)
+        };
+        furthest.min(0).unsigned_abs() + 1
+    }
+
+    /// Grows or shrinks every row's history to what the windows need, keeping
+    /// the newest steps.
+    fn resize_history(&mut self) {
+        let len = self.history_len();
+        for h in self.history.iter_mut() {
+            h.resize(len, 0);
+        }
+    }
+}
+
+fn future_steps(steps_on_screen: usize, future_position: f32) -> usize {
+    (steps_on_screen as f32 * future_position) as usize
+}
+
+fn canvas(steps_on_screen: usize, windows: usize, future_position: f32) -> TimeWindow {
+    let steps = steps_on_screen * windows;
+    TimeWindow {
+        first: future_steps(steps, future_position) as f32 - steps as f32,
+        steps: steps as f32,
+    }
+}
+
+fn rejected<R: ToString>(addr: &'static str, reason: R) -> Rejected {
+    Rejected {
+        addr,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{MatrixMessage, TempoMessage};
+    use crate::panel::Role;
+
+    // four steps a second, so a quarter second is one step
+    const STEP: f32 = 0.25;
+
+    fn timeline(quantize: Quantize) -> Timeline {
+        let mut timeline = Timeline::new(
+            MIN_STEPS_ON_SCREEN,
+            quantize,
+            Tempo::new(60.0, 4, 0.0),
+            WheelCurve::default(),
+            vec![Panel::new(Role::Now)],
+            None,
+        );
+        timeline.set_matrix(Matrix::new(1, 4, vec![1, 2, 3, 4]).unwrap());
+        timeline
+    }
+
+    fn matrix(rows: usize, cols: usize, cells: Vec<i32>) -> Messages {
+        Messages::Matrix(MatrixMessage {
+            matrix: cells,
+            rows: Some(rows),
+            cols: Some(cols),
+        })
+    }
+
+    fn played(timeline: &Timeline, steps: isize) -> Vec<i32> {
+        (0..steps).map(|back| timeline.value(0, -back)).collect()
+    }
+
+    #[test]
+    fn long_frame_takes_every_step() {
+        let mut timeline = timeline(Quantize::Immediate);
+        assert_eq!(timeline.advance(3.0 * STEP).steps, 3);
+        assert_eq!(timeline.position(), 3);
+        assert_eq!(played(&timeline, 4), vec![4, 3, 2, 0]);
+    }
+
+    #[test]
+    fn huge_frame_only_replays_what_shows() {
+        let mut timeline = timeline(Quantize::Immediate);
+        let advance = timeline.advance(1e6);
+        assert_eq!(advance.steps, 4_000_000);
+        assert_eq!(timeline.position(), 0);
+        assert_eq!(played(&timeline, 5), vec![1, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn stopped_transport_takes_no_steps() {
+        let mut timeline = timeline(Quantize::Immediate);
+        timeline.set_transport(Transport::Stopped);
+        assert_eq!(timeline.advance(1.0).steps, 0);
+        assert_eq!(timeline.position(), 0);
+    }
+
+    #[test]
+    fn seek_fills_the_history_from_the_matrix() {
+        let mut timeline = timeline(Quantize::Immediate);
+        timeline.seek(6);
+        assert_eq!(timeline.position(), 2);
+        assert_eq!(played(&timeline, 5), vec![3, 2, 1, 4, 3]);
+        timeline.seek_back();
+        assert_eq!(timeline.position(), 1);
+        assert_eq!(played(&timeline, 2), vec![2, 1]);
+    }
+
+    #[test]
+    fn reset_empties_the_history() {
+        let mut timeline = timeline(Quantize::Immediate);
+        timeline.advance(2.0 * STEP);
+        timeline.reset();
+        assert_eq!(timeline.position(), 0);
+        assert_eq!(timeline.clock().beats(), 0.0);
+        assert_eq!(played(&timeline, 3), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn immediate_matrix_applies_right_away() {
+        let mut timeline = timeline(Quantize::Immediate);
+        timeline.apply(matrix(1, 2, vec![7, 8])).unwrap();
+        assert!(timeline.pending().is_none());
+        assert_eq!(timeline.matrix().cells(), &[7, 8]);
+    }
+
+    #[test]
+    fn step_quantized_matrix_waits_for_the_next_step() {
+        let mut timeline = timeline(Quantize::Step);
+        timeline.advance(STEP);
+        timeline.apply(matrix(1, 2, vec![7, 8])).unwrap();
+        assert_eq!(timeline.matrix().cells(), &[1, 2, 3, 4]);
+        timeline.advance(0.5 * STEP);
+        assert!(timeline.pending().is_some());
+        timeline.advance(0.5 * STEP);
+        assert!(timeline.pending().is_none());
+        assert_eq!(timeline.matrix().cells(), &[7, 8]);
+        assert_eq!(timeline.position(), 0);
+    }
+
+    #[test]
+    fn cycle_quantized_matrix_waits_for_the_cycle_start() {
+        let mut timeline = timeline(Quantize::Cycle);
+        timeline.advance(STEP);
+        timeline.apply(matrix(1, 2, vec![7, 8])).unwrap();
+        timeline.advance(2.0 * STEP);
+        assert_eq!(timeline.position(), 3);
+        assert!(timeline.pending().is_some());
+        timeline.advance(STEP);
+        assert_eq!(timeline.position(), 0);
+        assert_eq!(timeline.matrix().cells(), &[7, 8]);
+        // the step that brought it in already plays the new matrix
+        assert_eq!(played(&timeline, 2), vec![7, 4]);
+    }
+
+    #[test]
+    fn newer_matrix_replaces_a_pending_one() {
+        let mut timeline = timeline(Quantize::Cycle);
+        timeline.apply(matrix(1, 2, vec![7, 8])).unwrap();
+        timeline.apply(matrix(1, 3, vec![5, 6, 7])).unwrap();
+        timeline.advance(4.0 * STEP);
+        assert_eq!(timeline.matrix().cells(), &[5, 6, 7]);
+    }
+
+    #[test]
+    fn bad_shapes_are_rejected() {
+        let mut timeline = timeline(Quantize::Immediate);
+        assert!(timeline.apply(matrix(2, 2, vec![1, 2, 3])).is_err());
+        assert!(timeline.apply(matrix(0, 2, vec![])).is_err());
+        assert!(timeline.apply(matrix(1 << 63, 2, vec![])).is_err());
+        assert!(timeline
+            .apply(matrix(2, usize::MAX / 2 + 1, vec![]))
+            .is_err());
+        assert_eq!(timeline.matrix().cells(), &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn tempo_out_of_range_is_rejected() {
+        let mut timeline = timeline(Quantize::Immediate);
+        let tempo = |bpm, subdivision| Messages::Tempo(TempoMessage { bpm, subdivision });
+        assert!(timeline.apply(tempo(1e30, None)).is_err());
+        assert!(timeline.apply(tempo(f32::NAN, None)).is_err());
+        assert!(timeline.apply(tempo(120.0, Some(0))).is_err());
+        assert!(timeline.apply(tempo(120.0, Some(1 << 20))).is_err());
+        assert!(timeline.apply(tempo(120.0, Some(2))).is_ok());
+        assert_eq!(timeline.tempo().subdivision(), 2);
+    }
+}
@@ -0,0 +1,20 @@
+/// Whether the timeline is moving.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transport {
+    Playing,
+    /// Nothing scrolls and no steps are taken; ticks from the server are ignored.
+    Stopped,
+}
+
+impl Transport {
+    pub fn is_playing(&self) -> bool {
+        *self == Transport::Playing
+    }
+
+    pub fn toggled(&self) -> Self {
+        match self {
+            Transport::Playing => Transport::Stopped,
+            Transport::Stopped => Transport::Playing,
+        }
+    }
+}
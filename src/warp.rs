@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Corner-pin warp of one window, to make an off-axis projection look
+/// rectangular on the wall.
+///
+/// Positions are fractions of the window, from (0, 0) at the bottom left to
+/// (1, 1) at the top right. `corners` says where the window's own corners end
+/// up, in the order bottom left, bottom right, top right, top left.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Warp {
+    pub corners: [[f32; 2]; 4],
+}
+
+impl Default for Warp {
+    fn default() -> Self {
+        Self {
+            corners: [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+        }
+    }
+}
+
+impl Warp {
+    /// Where the point at `(u, v)` of the window ends up.
+    pub fn map(&self, u: f32, v: f32) -> (f32, f32) {
+        let [a, b, c, d, e, f, g, h] = self.coefficients();
+        let w = g * u + h * v + 1.0;
+        ((a * u + b * v + c) / w, (d * u + e * v + f) / w)
+    }
+
+    /// The corner closest to `(u, v)`.
+    pub fn nearest_corner(&self, u: f32, v: f32) -> usize {
+        let distance = |i: usize| {
+            let [x, y] = self.corners[i];
+            (x - u).powi(2) + (y - v).powi(2)
+        };
+        (0..4)
+            .min_by(|&i, &j| distance(i).partial_cmp(&distance(j)).unwrap())
+            .unwrap_or(0)
+    }
+
+    pub fn move_corner(&mut self, corner: usize, u: f32, v: f32) {
+        self.corners[corner % 4] = [u, v];
+    }
+
+    pub fn nudge(&mut self, corner: usize, du: f32, dv: f32) {
+        let [u, v] = self.corners[corner % 4];
+        self.move_corner(corner, u + du, v + dv);
+    }
+
+    /// The projective transform taking the unit square to `corners`, as
+    /// `x = (a u + b v + c) / (g u + h v + 1)` and `y = (d u + e v + f) / (...)`.
+    fn coefficients(&self) -> [f32; 8] {
+        let [[x0, y0], [x1, y1], [x2, y2], [x3, y3]] = self.corners;
+        let (dx3, dy3) = (x0 - x1 + x2 - x3, y0 - y1 + y2 - y3);
+        if dx3 == 0.0 && dy3 == 0.0 {
+            // a parallelogram, no perspective needed
+            return [x1 - x0, x2 - x1, x0, y1 - y0, y2 - y1, y0, 0.0, 0.0];
+        }
+        let (dx1, dy1) = (x1 - x2, y1 - y2);
+        let (dx2, dy2) = (x3 - x2, y3 - y2);
+        let den = dx1 * dy2 - dx2 * dy1;
+        if den == 0.0 {
+            // three corners on a line; leave the picture as it is
+            return Self::default().coefficients();
+        }
+        let g = (dx3 * dy2 - dx2 * dy3) / den;
+        let h = (dx1 * dy3 - dx3 * dy1) / den;
+        [
+            x1 - x0 + g * x1,
+            x3 - x0 + h * x3,
+            x0,
+            y1 - y0 + g * y1,
+            y3 - y0 + h * y3,
+            y0,
+            g,
+            h,
+        ]
+    }
+}
+
+/// Reads the warps saved with `save`, one per window.
+pub fn load(path: &Path) -> io::Result<Vec<Warp>> {
+    let text = fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn save(path: &Path, warps: &[Warp]) -> io::Result<()> {
+    let text = serde_json::to_string_pretty(warps)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(path, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNIT: [[f32; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+
+    fn assert_near((x, y): (f32, f32), [ex, ey]: [f32; 2]) {
+        assert!(
+            (x - ex).abs() < 1e-5 && (y - ey).abs() < 1e-5,
+            "({}, {}) is not near ({}, {})",
+            x,
+            y,
+            ex,
+            ey
+        );
+    }
+
+    #[test]
+    fn unit_corners_land_on_a_skewed_quad() {
+        let warp = Warp {
+            corners: [[0.1, 0.05], [0.95, 0.2], [0.8, 0.9], [0.05, 0.85]],
+        };
+        for (&[u, v], corner) in UNIT.iter().zip(warp.corners) {
+            assert_near(warp.map(u, v), corner);
+        }
+        // the perspective pulls the middle off the corners' average
+        let (x, _) = warp.map(0.5, 0.5);
+        assert!((x - 0.475).abs() > 1e-3);
+    }
+
+    #[test]
+    fn parallelogram_maps_linearly() {
+        let warp = Warp {
+            corners: [[0.0, 0.0], [0.5, 0.25], [0.75, 1.0], [0.25, 0.75]],
+        };
+        assert_eq!(warp.coefficients()[6..], [0.0, 0.0]);
+        for (&[u, v], corner) in UNIT.iter().zip(warp.corners) {
+            assert_near(warp.map(u, v), corner);
+        }
+        assert_near(warp.map(0.5, 0.5), [0.375, 0.5]);
+        assert_near(Warp::default().map(0.25, 0.75), [0.25, 0.75]);
+    }
+
+    #[test]
+    fn collinear_corners_leave_the_picture_alone() {
+        let warp = Warp {
+            corners: [[0.0, 0.0], [1.0, 0.0], [0.5, 0.5], [0.0, 1.0]],
+        };
+        assert_eq!(warp.coefficients(), Warp::default().coefficients());
+        assert_near(warp.map(0.3, 0.6), [0.3, 0.6]);
+    }
+}